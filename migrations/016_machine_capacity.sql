@@ -0,0 +1,3 @@
+-- Production time (in seconds) each machine has available per simulation day
+ALTER TABLE machines
+ADD COLUMN day_capacity int NOT NULL DEFAULT 60 CHECK (day_capacity > 0);
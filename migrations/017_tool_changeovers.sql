@@ -0,0 +1,20 @@
+-- Time (in seconds) a machine needs to swap one of its tools for another
+CREATE TABLE IF NOT EXISTS tool_changeovers (
+  machine char(2) NOT NULL,
+  from_tool char(2) NOT NULL,
+  to_tool char(2) NOT NULL,
+  changeover_time int NOT NULL CHECK (changeover_time >= 0),
+
+  PRIMARY KEY (machine, from_tool, to_tool),
+  FOREIGN KEY (machine, from_tool) REFERENCES machine_tools(machine, tool),
+  FOREIGN KEY (machine, to_tool) REFERENCES machine_tools(machine, tool),
+  CHECK (from_tool <> to_tool)
+);
+
+INSERT INTO tool_changeovers (machine, from_tool, to_tool, changeover_time)
+SELECT a.machine, a.tool, b.tool, 10
+FROM machine_tools AS a
+JOIN machine_tools AS b ON a.machine = b.machine AND a.tool <> b.tool;
+
+ALTER TABLE transformations
+ADD COLUMN changeover_time int NOT NULL DEFAULT 0 CHECK (changeover_time >= 0);
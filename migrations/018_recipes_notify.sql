@@ -0,0 +1,12 @@
+-- Lets the scheduler know it has to reload its in-memory recipe graph
+CREATE FUNCTION notify_recipes_changed() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM pg_notify('recipes_changed', '');
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER recipes_changed
+AFTER INSERT OR UPDATE OR DELETE ON recipes
+FOR EACH STATEMENT
+EXECUTE FUNCTION notify_recipes_changed();
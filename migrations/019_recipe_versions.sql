@@ -0,0 +1,15 @@
+-- Recipes are never edited in place so that transformations that were
+-- already planned keep pointing at the recipe they were planned with.
+-- Editing a recipe retires the old row and inserts a new one.
+ALTER TABLE recipes
+ADD COLUMN active boolean NOT NULL DEFAULT true;
+
+ALTER TABLE recipes
+DROP CONSTRAINT recipes_material_kind_product_kind_tool_key;
+
+CREATE UNIQUE INDEX recipes_active_material_product_tool
+ON recipes (material_kind, product_kind, tool)
+WHERE active;
+
+ALTER TABLE recipes
+ADD CHECK (operation_time > 0);
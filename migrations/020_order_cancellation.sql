@@ -0,0 +1,51 @@
+ALTER TYPE transformation_status ADD VALUE 'canceled';
+
+ALTER TABLE orders
+ADD COLUMN cancellation_reason text;
+
+-- Stock allocated to an order gets the id of the item it replaces, see
+-- upsert_item. The shipment the stock arrived in and the transformations of
+-- a canceled order that would have consumed it follow the new id, so that
+-- canceled transformations are kept as a record of the cancellation.
+ALTER TABLE raw_material_shipments
+DROP CONSTRAINT raw_material_shipments_raw_material_id_fkey,
+ADD FOREIGN KEY (raw_material_id) REFERENCES items(id) ON UPDATE CASCADE;
+
+ALTER TABLE transformations
+DROP CONSTRAINT transformations_material_id_fkey,
+ADD FOREIGN KEY (material_id) REFERENCES items(id) ON UPDATE CASCADE;
+
+-- References to the swapped id are now updated by the foreign keys
+CREATE OR REPLACE FUNCTION upsert_item()
+RETURNS TRIGGER AS $$
+  DECLARE free_stock RECORD;
+  BEGIN
+    IF NEW.order_id IS NULL THEN
+      RETURN NEW; -- Insert as usual, no need to allocate stock
+    END IF;
+
+    IF NEW.piece_kind NOT IN ( SELECT code FROM pieces WHERE category = 'raw') THEN
+      RETURN NEW; -- Insert as usual, we are only looking for raw materials
+    END IF;
+
+    SELECT * INTO free_stock FROM items As i
+    WHERE i.piece_kind = NEW.piece_kind
+      AND i.status = 'in_stock'
+      AND i.order_id IS NULL
+    LIMIT 1;
+
+    IF NOT FOUND THEN
+      RETURN NEW; -- Insert as usual
+    END IF;
+
+    UPDATE items
+    SET id = new.id, -- HACK: this is a workaround to not break the application code
+    order_id = new.order_id
+    WHERE id = free_stock.id;
+
+    RAISE NOTICE 'Item % alocated from existing stock to order %', free_stock.id, new.order_id;
+
+    RETURN NULL; -- Do not insert, we updated an existing item
+  END;
+$$
+LANGUAGE plpgsql;
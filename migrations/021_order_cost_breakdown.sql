@@ -0,0 +1,50 @@
+-- Cost of each order split by origin. Raw material is charged at the unit
+-- price of the shipment it arrived in and depreciates 1% a day while stored,
+-- production cost is what was accumulated on the final items. Until an order
+-- is delivered its planned delivery day is used.
+CREATE OR REPLACE VIEW order_cost_breakdown AS (
+  WITH final_items AS (
+    SELECT
+      o.id AS order_id,
+      o.delivery_day,
+      i.acc_cost,
+      get_raw_material(i.id) AS raw_material_id
+    FROM orders AS o
+    JOIN items AS i
+        ON i.order_id = o.id
+    WHERE i.piece_kind = o.piece
+  ),
+  item_costs AS (
+    SELECT
+      f.order_id,
+      f.acc_cost AS production_cost,
+      COALESCE(s.cost / s.quantity, 0::money) AS raw_material_cost,
+      COALESCE(
+        CAST(CAST(s.cost / s.quantity AS numeric)
+            * GREATEST(f.delivery_day - s.arrival_date, 0) * 0.01 AS money),
+        0::money
+      ) AS storage_cost
+    FROM final_items AS f
+    LEFT JOIN raw_material_shipments AS rs
+        ON rs.raw_material_id = f.raw_material_id
+    LEFT JOIN shipments AS s
+        ON s.id = rs.shipment_id
+  )
+  SELECT
+    o.id AS order_id,
+    COALESCE(SUM(c.raw_material_cost), 0::money) AS raw_material_cost,
+    COALESCE(SUM(c.production_cost), 0::money) AS production_cost,
+    COALESCE(SUM(c.storage_cost), 0::money) AS storage_cost,
+    CASE
+      WHEN o.delivery_day IS NULL
+        THEN 0::money
+      WHEN o.delivery_day > o.due_date
+        THEN o.late_penalty * (o.delivery_day - o.due_date)
+      ELSE o.early_penalty * (o.due_date - o.delivery_day)
+    END AS penalty
+  FROM orders AS o
+  LEFT JOIN item_costs AS c
+      ON c.order_id = o.id
+  GROUP BY o.id
+);
+
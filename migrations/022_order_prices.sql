@@ -0,0 +1,17 @@
+-- Selling price of one piece, unknown for orders placed before prices were
+-- recorded
+ALTER TABLE orders
+ADD COLUMN unit_price money CHECK (unit_price >= 0::money);
+
+-- Profit of delivered orders with a known selling price
+CREATE OR REPLACE VIEW order_profits AS (
+  SELECT
+    o.id AS order_id,
+    o.unit_price * o.quantity AS revenue,
+    f.cost,
+    o.unit_price * o.quantity - f.cost AS profit
+  FROM order_final_costs AS f
+  JOIN orders AS o
+      ON o.id = f.order_id
+  WHERE o.unit_price IS NOT NULL
+);
@@ -0,0 +1,16 @@
+-- Orders received again with the number of an existing order of the same
+-- client but with different content, kept for an operator to review
+CREATE TABLE IF NOT EXISTS order_conflicts (
+  id bigint PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  order_id uuid NOT NULL REFERENCES orders(id),
+
+  piece piece_kind NOT NULL REFERENCES pieces(code),
+  quantity int NOT NULL,
+  due_date int NOT NULL,
+  late_penalty money NOT NULL,
+  early_penalty money NOT NULL,
+  unit_price money,
+
+  received_day int NOT NULL,
+  received_at timestamptz NOT NULL DEFAULT now()
+);
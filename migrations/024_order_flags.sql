@@ -0,0 +1,4 @@
+-- Validation rules broken by an order that was accepted anyway, so that it
+-- can be reviewed
+ALTER TABLE orders
+ADD COLUMN flags text[] NOT NULL DEFAULT '{}';
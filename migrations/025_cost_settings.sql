@@ -0,0 +1,92 @@
+-- Cost model parameters, kept in the database since the cost views use them.
+-- The application writes its configured values on startup.
+CREATE TABLE IF NOT EXISTS cost_settings (
+  id bool PRIMARY KEY DEFAULT true CHECK (id),
+  -- share of the raw material cost charged for each day it is stored
+  holding_cost_rate numeric NOT NULL DEFAULT 0.01 CHECK (holding_cost_rate >= 0)
+);
+
+INSERT INTO cost_settings DEFAULT VALUES;
+
+CREATE OR REPLACE FUNCTION holding_cost_rate() RETURNS numeric AS $$
+  SELECT holding_cost_rate FROM cost_settings;
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION item_cost(item_id uuid) RETURNS money AS $$
+DECLARE raw_material RECORD;
+        material_cost MONEY;
+        material_arrival_date INT;
+        dispatch_date INT;
+        accumulated_cost MONEY;
+BEGIN
+  SELECT acc_cost INTO accumulated_cost
+  FROM items
+  WHERE id = item_id;
+
+  SELECT * INTO raw_material
+  FROM items
+  WHERE id = get_raw_material(item_id);
+
+  SELECT delivery_day INTO dispatch_date
+  FROM orders
+  WHERE id = raw_material.order_Id;
+
+  SELECT s.cost, s.arrival_date INTO material_cost, material_arrival_date
+  FROM shipments AS s
+  JOIN raw_material_shipments AS rs
+      ON rs.shipment_id = s.id
+  WHERE rs.raw_material_id = raw_material.id;
+
+  RETURN accumulated_cost + material_cost +
+        CAST(CAST(material_cost AS numeric)
+            * (dispatch_date - material_arrival_date)
+            * holding_cost_rate() AS MONEY);
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE VIEW order_cost_breakdown AS (
+  WITH final_items AS (
+    SELECT
+      o.id AS order_id,
+      o.delivery_day,
+      i.acc_cost,
+      get_raw_material(i.id) AS raw_material_id
+    FROM orders AS o
+    JOIN items AS i
+        ON i.order_id = o.id
+    WHERE i.piece_kind = o.piece
+  ),
+  item_costs AS (
+    SELECT
+      f.order_id,
+      f.acc_cost AS production_cost,
+      COALESCE(s.cost / s.quantity, 0::money) AS raw_material_cost,
+      COALESCE(
+        CAST(CAST(s.cost / s.quantity AS numeric)
+            * GREATEST(f.delivery_day - s.arrival_date, 0)
+            * holding_cost_rate() AS money),
+        0::money
+      ) AS storage_cost
+    FROM final_items AS f
+    LEFT JOIN raw_material_shipments AS rs
+        ON rs.raw_material_id = f.raw_material_id
+    LEFT JOIN shipments AS s
+        ON s.id = rs.shipment_id
+  )
+  SELECT
+    o.id AS order_id,
+    COALESCE(SUM(c.raw_material_cost), 0::money) AS raw_material_cost,
+    COALESCE(SUM(c.production_cost), 0::money) AS production_cost,
+    COALESCE(SUM(c.storage_cost), 0::money) AS storage_cost,
+    CASE
+      WHEN o.delivery_day IS NULL
+        THEN 0::money
+      WHEN o.delivery_day > o.due_date
+        THEN o.late_penalty * (o.delivery_day - o.due_date)
+      ELSE o.early_penalty * (o.due_date - o.delivery_day)
+    END AS penalty
+  FROM orders AS o
+  LEFT JOIN item_costs AS c
+      ON c.order_id = o.id
+  GROUP BY o.id
+);
@@ -0,0 +1,99 @@
+-- Items held by each warehouse, only items in stock are stored in warehouses
+CREATE VIEW warehouse_occupancy AS
+SELECT w.code, w.capacity, COUNT(i.id)::int AS occupancy
+FROM warehouses AS w
+LEFT JOIN items AS i
+  ON i.location = w.code
+  AND i.status = 'in_stock'
+GROUP BY w.code, w.capacity;
+
+-- Warehouse with the most free slots, NULL when every warehouse is full.
+-- The warehouses stay locked until the end of the transaction so that
+-- concurrent entries cannot overfill them.
+CREATE FUNCTION free_warehouse() RETURNS char(2) AS $$
+DECLARE free_code char(2);
+  BEGIN
+    PERFORM 1 FROM warehouses ORDER BY code FOR UPDATE;
+
+    SELECT o.code INTO free_code
+    FROM warehouse_occupancy AS o
+    WHERE o.occupancy < o.capacity
+    ORDER BY o.capacity - o.occupancy DESC, o.code
+    LIMIT 1;
+
+    RETURN free_code;
+  END;
+$$ LANGUAGE plpgsql;
+
+-- Arriving items are spread over the warehouses with free slots instead of
+-- all being stored in W1. When every warehouse is full the arrival is still
+-- recorded: the remaining items wait at the dock, in transit without a
+-- location, until they are entered in a warehouse.
+CREATE OR REPLACE FUNCTION shipment_arrived() RETURNS TRIGGER AS $$
+DECLARE item_price money;
+        item_ids uuid[];
+        item_id uuid;
+        new_item_id uuid;
+        p_kind char(2);
+        n_missing_items int;
+        warehouse char(2);
+  BEGIN
+    SELECT unit_price, CAST(raw_material_kind AS char(2))
+      INTO item_price, p_kind
+    FROM suppliers
+    JOIN shipments AS sh ON sh.supplier_id = suppliers.id
+    WHERE sh.id = NEW.id;
+
+    SELECT ARRAY_AGG(items.id) INTO item_ids
+    FROM items
+    JOIN raw_material_shipments AS rs
+        ON rs.raw_material_id = items.id
+    JOIN shipments AS s
+        ON rs.shipment_id = s.id
+    WHERE s.id = NEW.id;
+
+    IF array_length(item_ids, 1) > 0 THEN
+      FOREACH item_id IN ARRAY item_ids LOOP
+        warehouse := free_warehouse();
+        IF warehouse IS NULL THEN
+          RAISE WARNING 'Every warehouse is full, item % waits at the dock', item_id;
+          UPDATE items
+          SET status = 'in_transit',
+            location = NULL
+          WHERE id = item_id;
+          CONTINUE;
+        END IF;
+
+        RAISE NOTICE 'Item % arrived in %', item_id, warehouse;
+        UPDATE items
+        SET status = 'in_stock',
+          location = warehouse
+        WHERE id = item_id;
+      END LOOP;
+    END IF;
+
+    SELECT NEW.quantity - array_length(item_ids, 1) INTO n_missing_items;
+    IF n_missing_items > 0 THEN
+      RAISE NOTICE 'Missing items: %', n_missing_items;
+      FOR i IN 1..n_missing_items
+      LOOP
+        warehouse := free_warehouse();
+        IF warehouse IS NULL THEN
+          INSERT INTO items (piece_kind, status)
+          VALUES (CAST(p_kind AS piece_kind), 'in_transit')
+          RETURNING id INTO new_item_id;
+          RAISE WARNING 'Every warehouse is full, item % waits at the dock', new_item_id;
+          CONTINUE;
+        END IF;
+
+        INSERT INTO items (piece_kind, status, location)
+        VALUES (CAST(p_kind AS piece_kind), 'in_stock', warehouse)
+        RETURNING id INTO new_item_id;
+      END LOOP;
+
+      RAISE NOTICE '% free items added of type %', n_missing_items, p_kind;
+    END IF;
+
+    RETURN NEW;
+  END;
+$$ LANGUAGE plpgsql;
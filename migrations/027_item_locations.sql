@@ -0,0 +1,20 @@
+-- Items can only be in a warehouse or on a production line, reported as a
+-- foreign key violation so that writers get the same error as for other
+-- unknown references
+CREATE FUNCTION check_location()
+RETURNS TRIGGER AS $$
+  BEGIN
+    IF NEW.location IS NOT NULL
+      AND NEW.location NOT IN (SELECT code FROM production_lines UNION SELECT code FROM warehouses)
+    THEN
+      RAISE EXCEPTION 'Location % does not exist', NEW.location
+      USING ERRCODE = 'foreign_key_violation';
+    END IF;
+    RETURN NEW;
+  END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER check_location
+BEFORE INSERT OR UPDATE OF location ON items
+FOR EACH ROW
+EXECUTE FUNCTION check_location();
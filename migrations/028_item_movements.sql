@@ -0,0 +1,67 @@
+-- Append-only log of the status and location changes of every item. The
+-- actor is the route or task that caused the change, set for the current
+-- transaction with set_config('app.actor', ..., true).
+CREATE TABLE IF NOT EXISTS item_movements (
+  id bigint PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+  -- ids of free stock change when it is allocated to an order
+  item_id uuid NOT NULL REFERENCES items(id) ON UPDATE CASCADE,
+  day int NOT NULL,
+  actor text,
+  previous_status item_status,
+  status item_status NOT NULL,
+  previous_location char(2),
+  location char(2)
+);
+
+CREATE INDEX IF NOT EXISTS item_movements_item_id ON item_movements (item_id);
+
+CREATE FUNCTION record_item_movement()
+RETURNS TRIGGER AS $$
+  DECLARE previous_status item_status;
+          previous_location char(2);
+  BEGIN
+    IF TG_OP = 'UPDATE' THEN
+      IF NEW.status = OLD.status
+        AND NEW.location IS NOT DISTINCT FROM OLD.location
+      THEN
+        RETURN NEW;
+      END IF;
+
+      previous_status := OLD.status;
+      previous_location := OLD.location;
+    END IF;
+
+    INSERT INTO item_movements (
+      item_id, day, actor, previous_status, status, previous_location, location
+    ) VALUES (
+      NEW.id,
+      (SELECT simulation_date FROM epoch_table),
+      NULLIF(current_setting('app.actor', true), ''),
+      previous_status,
+      NEW.status,
+      previous_location,
+      NEW.location
+    );
+
+    RETURN NEW;
+  END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER record_item_movement
+AFTER INSERT OR UPDATE OF status, location ON items
+FOR EACH ROW
+EXECUTE FUNCTION record_item_movement();
+
+CREATE FUNCTION forbid_item_movement_changes()
+RETURNS TRIGGER AS $$
+  BEGIN
+    RAISE EXCEPTION 'Item movements cannot be changed';
+  END
+$$ LANGUAGE plpgsql;
+
+-- item_id is left out so that it follows the ids of the items
+CREATE TRIGGER forbid_item_movement_changes
+BEFORE DELETE OR UPDATE OF id, day, actor, previous_status, status, previous_location, location
+ON item_movements
+FOR EACH ROW
+EXECUTE FUNCTION forbid_item_movement_changes();
@@ -0,0 +1,36 @@
+-- shipments.cost is the price of the whole shipment, yet item_cost charged it
+-- to every item it brought in. Each item is now charged its share of the
+-- shipment, like in order_cost_breakdown, which changes the final cost of
+-- orders already delivered.
+CREATE OR REPLACE FUNCTION item_cost(item_id uuid) RETURNS money AS $$
+DECLARE raw_material RECORD;
+        material_cost MONEY;
+        material_arrival_date INT;
+        dispatch_date INT;
+        accumulated_cost MONEY;
+BEGIN
+  SELECT acc_cost INTO accumulated_cost
+  FROM items
+  WHERE id = item_id;
+
+  SELECT * INTO raw_material
+  FROM items
+  WHERE id = get_raw_material(item_id);
+
+  SELECT delivery_day INTO dispatch_date
+  FROM orders
+  WHERE id = raw_material.order_Id;
+
+  SELECT s.cost / s.quantity, s.arrival_date
+    INTO material_cost, material_arrival_date
+  FROM shipments AS s
+  JOIN raw_material_shipments AS rs
+      ON rs.shipment_id = s.id
+  WHERE rs.raw_material_id = raw_material.id;
+
+  RETURN accumulated_cost + material_cost +
+        CAST(CAST(material_cost AS numeric)
+            * (dispatch_date - material_arrival_date)
+            * holding_cost_rate() AS MONEY);
+END;
+$$ LANGUAGE plpgsql;
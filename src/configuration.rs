@@ -1,35 +1,265 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use config::Config;
 use sqlx::{migrate, Connection, PgPool};
 
+use crate::db_api::FinalPiece;
+
+/// Reads the settings from, in order of precedence:
+///
+/// - `APP_` prefixed environment variables, with `__` between the names of
+///   nested fields, e.g. `APP_APPLICATION__HTTP_PORT=8080`. Lists are comma
+///   separated.
+/// - `DATABASE_URL`, which replaces the other database settings.
+/// - `configuration.{APP_ENVIRONMENT}.yml`, if it exists. `APP_ENVIRONMENT`
+///   is `local` by default.
+/// - `configuration.yml`
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+    let environment: Environment = std::env::var("APP_ENVIRONMENT")
+        .unwrap_or_else(|_| "local".to_string())
+        .try_into()
+        .map_err(config::ConfigError::Message)?;
+
     let settings = Config::builder()
         .add_source(config::File::new(
             "configuration.yml",
             config::FileFormat::Yaml,
         ))
+        .add_source(
+            config::File::new(
+                &format!("configuration.{}.yml", environment.as_str()),
+                config::FileFormat::Yaml,
+            )
+            .required(false),
+        )
+        .set_override_option(
+            "database.url",
+            std::env::var("DATABASE_URL").ok(),
+        )?
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true)
+                .list_separator(",")
+                .with_list_parse_key("application.udp_hosts")
+                .with_list_parse_key("application.udp_allowed_sources")
+                .with_list_parse_key("application.tcp_hosts"),
+        )
         .build()?;
 
-    settings.try_deserialize()
+    let settings: Settings = settings.try_deserialize()?;
+    settings
+        .scheduler
+        .check()
+        .map_err(config::ConfigError::Message)?;
+
+    Ok(settings)
+}
+
+/// Deployment the configuration is read for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Environment {
+    Local,
+    Production,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Production => "production",
+        }
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "production" => Ok(Self::Production),
+            other => Err(format!(
+                "{} is not a supported environment, use either `local` or \
+                `production`",
+                other
+            )),
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
+    #[serde(default)]
+    pub pricing: PricingSettings,
+    #[serde(default)]
+    pub validation: ValidationSettings,
+    #[serde(default)]
+    pub scheduler: SchedulerSettings,
 }
 
 #[derive(serde::Deserialize)]
 pub struct ApplicationSettings {
+    /// addresses the UDP listener binds to, IPv4 or IPv6
+    #[serde(default = "ApplicationSettings::default_udp_hosts")]
+    pub udp_hosts: Vec<IpAddr>,
     pub udp_port: u16,
     pub udp_buffer_size: usize,
+    /// only accept UDP commands sent from these addresses, if any are set
+    #[serde(default)]
+    pub udp_allowed_sources: Vec<IpAddr>,
     pub http_port: u16,
     pub http_host: String,
+    /// accept orders on `POST /orders`
+    #[serde(default)]
+    pub http_order_intake: bool,
+    /// addresses the TCP listener binds to, IPv4 or IPv6
+    #[serde(default = "ApplicationSettings::default_tcp_hosts")]
+    pub tcp_hosts: Vec<IpAddr>,
+    /// the TCP listener only runs when a port is set
+    #[serde(default)]
+    pub tcp_port: Option<u16>,
+    #[serde(default = "ApplicationSettings::default_tcp_max_message_size")]
+    pub tcp_max_message_size: usize,
+}
+
+impl ApplicationSettings {
+    fn default_udp_hosts() -> Vec<IpAddr> {
+        vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+    }
+
+    fn default_tcp_hosts() -> Vec<IpAddr> {
+        vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+    }
+
+    fn default_tcp_max_message_size() -> usize {
+        1 << 20
+    }
+}
+
+/// Selling prices used for orders that do not state their own.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct PricingSettings {
+    #[serde(default)]
+    pub unit_prices: Vec<PiecePrice>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PiecePrice {
+    pub piece: FinalPiece,
+    /// in cents
+    pub unit_price: i64,
+}
+
+impl PricingSettings {
+    pub fn unit_price(&self, piece: FinalPiece) -> Option<i64> {
+        self.unit_prices
+            .iter()
+            .find(|p| p.piece == piece)
+            .map(|p| p.unit_price)
+    }
+}
+
+/// What to do with an order that breaks a validation rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationPolicy {
+    Reject,
+    /// accept the order and log a warning
+    Warn,
+    /// accept the order, log a warning and flag the order for review
+    Flag,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct ValidationSettings {
+    pub min_quantity: i32,
+    pub max_quantity: i32,
+    /// quantity outside of `min_quantity..=max_quantity`
+    pub quantity: ValidationPolicy,
+    /// due date not after the current day
+    pub due_date: ValidationPolicy,
+    /// due date too soon to buy the material and produce the order
+    pub lead_time: ValidationPolicy,
+    /// negative penalties
+    pub penalties: ValidationPolicy,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            min_quantity: 1,
+            max_quantity: 24,
+            quantity: ValidationPolicy::Reject,
+            due_date: ValidationPolicy::Reject,
+            lead_time: ValidationPolicy::Flag,
+            penalties: ValidationPolicy::Reject,
+        }
+    }
+}
+
+/// Tuning of the production planning and of the cost model.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct SchedulerSettings {
+    /// percentage of the operation time spent on logistics, added to the
+    /// time booked on the machines
+    pub logistics_time_factor: i64,
+    /// days between an order being scheduled and its first transformation,
+    /// so that materials can be prepared
+    pub start_offset_days: i32,
+    /// share of the raw material cost charged for each day it is stored
+    pub holding_cost_rate: f64,
+    /// seconds of production each machine has in a simulation day
+    pub day_capacity: i32,
+}
+
+impl Default for SchedulerSettings {
+    fn default() -> Self {
+        Self {
+            logistics_time_factor: 50,
+            start_offset_days: 1,
+            holding_cost_rate: 0.01,
+            day_capacity: 60,
+        }
+    }
+}
+
+impl SchedulerSettings {
+    fn check(&self) -> Result<(), String> {
+        if self.logistics_time_factor < 0 {
+            return Err("scheduler.logistics_time_factor cannot be negative"
+                .to_string());
+        }
+        if self.start_offset_days < 0 {
+            return Err(
+                "scheduler.start_offset_days cannot be negative".to_string()
+            );
+        }
+        if self.holding_cost_rate < 0.0 {
+            return Err(
+                "scheduler.holding_cost_rate cannot be negative".to_string()
+            );
+        }
+        if self.day_capacity <= 0 {
+            return Err("scheduler.day_capacity must be positive".to_string());
+        }
+        Ok(())
+    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct DatabaseSettings {
+    /// full connection string, used instead of the other fields when set
+    #[serde(default)]
+    pub url: Option<String>,
     pub username: String,
     pub password: String,
     pub port: u16,
@@ -39,6 +269,10 @@ pub struct DatabaseSettings {
 
 impl DatabaseSettings {
     pub fn connection_string(&self) -> String {
+        if let Some(url) = &self.url {
+            return url.clone();
+        }
+
         format!(
             "postgres://{}:{}@{}:{}/{}",
             self.username,
@@ -50,6 +284,15 @@ impl DatabaseSettings {
     }
 
     pub fn connection_string_without_db(&self) -> String {
+        if let Some(url) = &self.url {
+            // the database name is the path following the host
+            let host_start = url.find("://").map_or(0, |i| i + 3);
+            return match url[host_start..].find('/') {
+                Some(i) => url[..host_start + i].to_string(),
+                None => url.clone(),
+            };
+        }
+
         format!(
             "postgres://{}:{}@{}:{}",
             self.username, self.password, self.host, self.port
@@ -89,3 +332,56 @@ impl DatabaseSettings {
         pool
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_url_without_db() {
+        let settings = DatabaseSettings {
+            url: Some("postgres://user:pw@db:5432/erp".to_string()),
+            username: "ignored".to_string(),
+            password: "ignored".to_string(),
+            port: 1,
+            host: "ignored".to_string(),
+            database_name: "ignored".to_string(),
+        };
+
+        assert_eq!(
+            settings.connection_string(),
+            "postgres://user:pw@db:5432/erp"
+        );
+        assert_eq!(
+            settings.connection_string_without_db(),
+            "postgres://user:pw@db:5432"
+        );
+    }
+
+    #[test]
+    fn scheduler_settings_are_checked() {
+        assert!(SchedulerSettings::default().check().is_ok());
+
+        let invalid = [
+            SchedulerSettings {
+                logistics_time_factor: -1,
+                ..Default::default()
+            },
+            SchedulerSettings {
+                start_offset_days: -1,
+                ..Default::default()
+            },
+            SchedulerSettings {
+                holding_cost_rate: -0.01,
+                ..Default::default()
+            },
+            SchedulerSettings {
+                day_capacity: 0,
+                ..Default::default()
+            },
+        ];
+        for settings in invalid {
+            assert!(settings.check().is_err(), "{settings:?}");
+        }
+    }
+}
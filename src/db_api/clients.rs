@@ -30,7 +30,10 @@ impl Client {
         con: &mut PgConnection,
     ) -> sqlx::Result<Uuid> {
         Ok(sqlx::query!(
-            "INSERT INTO clients (name) VALUES ($1) RETURNING id",
+            // a concurrent insert of the same client just returns its id
+            "INSERT INTO clients (name) VALUES ($1)
+            ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+            RETURNING id",
             name
         )
         .fetch_one(con)
@@ -39,7 +42,18 @@ impl Client {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// What happened to a received order, with the id of the order it refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ingestion {
+    Inserted(Uuid),
+    /// the same order was already received
+    Unchanged(Uuid),
+    /// an order with the same number but different content exists
+    Conflict(Uuid),
+}
+
+/// Order as sent by a client, money amounts are in cents.
+#[derive(Debug, PartialEq, Eq, serde::Deserialize)]
 pub struct ClientOrder {
     pub client_name: String,
     pub order_number: i32,
@@ -48,6 +62,12 @@ pub struct ClientOrder {
     pub due_date: i32,
     pub late_penalty: i64,
     pub early_penalty: i64,
+    /// selling price of one piece
+    #[serde(default)]
+    pub unit_price: Option<i64>,
+    /// codes of the validation rules the order was accepted despite breaking
+    #[serde(skip)]
+    pub flags: Vec<String>,
 }
 
 impl ClientOrder {
@@ -69,10 +89,54 @@ impl ClientOrder {
             due_date,
             late_penalty,
             early_penalty,
+            unit_price: None,
+            flags: Vec::new(),
         }
     }
 
-    pub async fn insert_to_db(&self, pool: &PgPool) -> sqlx::Result<Uuid> {
+    #[cfg(test)]
+    pub fn with_unit_price(mut self, unit_price: i64) -> Self {
+        self.unit_price = Some(unit_price);
+        self
+    }
+
+    /// Compares the order with the one the client already placed with the
+    /// same number, if any, keeping it as a conflict when they differ.
+    ///
+    /// Returns `None` for an order that was never received, so that only new
+    /// orders go through validation.
+    pub async fn find_resent(
+        &self,
+        pool: &PgPool,
+    ) -> sqlx::Result<Option<Ingestion>> {
+        let mut tx = pool.begin().await?;
+
+        let client_id =
+            match Client::query_by_name(&self.client_name, &mut tx).await? {
+                Some(c) => c.id,
+                None => return Ok(None),
+            };
+        let Some(existing) =
+            Order::find_by_number(client_id, self.order_number, &mut tx)
+                .await?
+        else {
+            return Ok(None);
+        };
+
+        let ingestion = self
+            .compare_with(existing, &self.to_order(client_id), &mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(Some(ingestion))
+    }
+
+    /// Inserts the order, unless the client already placed an order with the
+    /// same number.
+    ///
+    /// Commands are resent when delivery is unsure, so receiving the same
+    /// order again is not an error. An order that reuses a number with
+    /// different content is kept as a conflict for review.
+    pub async fn insert_to_db(&self, pool: &PgPool) -> sqlx::Result<Ingestion> {
         let mut tx = pool.begin().await?;
 
         // check if client exists in db
@@ -88,7 +152,32 @@ impl ClientOrder {
             }
         };
 
-        let new_order = Order::new(
+        let new_order = self.to_order(client_id);
+
+        let res = Order::insert_to_db(&new_order, &mut tx).await?;
+        if res.rows_affected() == 0 {
+            // received concurrently
+            let existing =
+                Order::find_by_number(client_id, self.order_number, &mut tx)
+                    .await?
+                    .ok_or(sqlx::Error::RowNotFound)?;
+            let ingestion =
+                self.compare_with(existing, &new_order, &mut tx).await?;
+            tx.commit().await?;
+            return Ok(ingestion);
+        }
+
+        Ntc::notify(Ntc::NewOrder, &new_order.id().to_string(), &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        tracing::info!("Inserted new order id: {}", new_order.id());
+
+        Ok(Ingestion::Inserted(new_order.id()))
+    }
+
+    fn to_order(&self, client_id: Uuid) -> Order {
+        Order::new(
             client_id,
             self.order_number,
             self.work_piece,
@@ -96,15 +185,28 @@ impl ClientOrder {
             self.due_date,
             self.early_penalty,
             self.late_penalty,
-        );
-        Order::insert_to_db(&new_order, &mut tx).await?;
-
-        Ntc::notify(Ntc::NewOrder, &new_order.id().to_string(), &mut tx)
-            .await?;
-        tx.commit().await?;
+        )
+        .with_unit_price(self.unit_price)
+        .with_flags(self.flags.clone())
+    }
 
-        tracing::info!("Inserted new order id: {}", new_order.id());
+    async fn compare_with(
+        &self,
+        existing: Order,
+        new_order: &Order,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Ingestion> {
+        if existing.same_request(new_order) {
+            return Ok(Ingestion::Unchanged(existing.id()));
+        }
 
-        Ok(new_order.id())
+        new_order.insert_conflict(existing.id(), con).await?;
+        tracing::warn!(
+            "Order {} of '{}' conflicts with order id: {}",
+            self.order_number,
+            self.client_name,
+            existing.id()
+        );
+        Ok(Ingestion::Conflict(existing.id()))
     }
 }
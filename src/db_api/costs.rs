@@ -0,0 +1,452 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use super::{FinalPiece, OrderStatus};
+
+/// Cost breakdown of an order, amounts are in cents.
+///
+/// Figures are only final once the order is delivered, before that the
+/// planned delivery day is used for storage and penalties.
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderCost {
+    pub order_id: Uuid,
+    pub client_name: String,
+    pub piece: FinalPiece,
+    pub quantity: i32,
+    pub status: OrderStatus,
+    pub raw_material_cost: i64,
+    pub production_cost: i64,
+    pub storage_cost: i64,
+    pub penalty: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct CostTotals {
+    pub orders: i64,
+    pub pieces: i64,
+    pub raw_material_cost: i64,
+    pub production_cost: i64,
+    pub storage_cost: i64,
+    pub penalty: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientCosts {
+    pub client_name: String,
+    #[serde(flatten)]
+    pub totals: CostTotals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PieceCosts {
+    pub piece: FinalPiece,
+    #[serde(flatten)]
+    pub totals: CostTotals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CostReport {
+    pub orders: Vec<OrderCost>,
+    pub by_client: Vec<ClientCosts>,
+    pub by_piece: Vec<PieceCosts>,
+    pub totals: CostTotals,
+}
+
+impl OrderCost {
+    pub async fn get_by_order(
+        order_id: Uuid,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as!(
+            OrderCost,
+            r#"
+            SELECT
+                o.id as order_id,
+                c.name as client_name,
+                o.piece as "piece: FinalPiece",
+                o.quantity,
+                o.status as "status: OrderStatus",
+                (b.raw_material_cost::numeric * 100)::bigint
+                    as "raw_material_cost!",
+                (b.production_cost::numeric * 100)::bigint
+                    as "production_cost!",
+                (b.storage_cost::numeric * 100)::bigint as "storage_cost!",
+                (b.penalty::numeric * 100)::bigint as "penalty!",
+                ((b.raw_material_cost + b.production_cost
+                    + b.storage_cost + b.penalty)::numeric * 100)::bigint
+                    as "total!"
+            FROM order_cost_breakdown AS b
+            JOIN orders AS o ON o.id = b.order_id
+            JOIN clients AS c ON c.id = o.client_id
+            WHERE o.id = $1
+            "#,
+            order_id
+        )
+        .fetch_optional(con)
+        .await
+    }
+
+    pub async fn get_delivered(
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            OrderCost,
+            r#"
+            SELECT
+                o.id as order_id,
+                c.name as client_name,
+                o.piece as "piece: FinalPiece",
+                o.quantity,
+                o.status as "status: OrderStatus",
+                (b.raw_material_cost::numeric * 100)::bigint
+                    as "raw_material_cost!",
+                (b.production_cost::numeric * 100)::bigint
+                    as "production_cost!",
+                (b.storage_cost::numeric * 100)::bigint as "storage_cost!",
+                (b.penalty::numeric * 100)::bigint as "penalty!",
+                ((b.raw_material_cost + b.production_cost
+                    + b.storage_cost + b.penalty)::numeric * 100)::bigint
+                    as "total!"
+            FROM order_cost_breakdown AS b
+            JOIN orders AS o ON o.id = b.order_id
+            JOIN clients AS c ON c.id = o.client_id
+            WHERE o.status = $1
+            ORDER BY o.delivery_day, c.name, o.number
+            "#,
+            OrderStatus::Delivered as OrderStatus
+        )
+        .fetch_all(con)
+        .await
+    }
+}
+
+impl CostTotals {
+    fn add(&mut self, cost: &OrderCost) {
+        self.orders += 1;
+        self.pieces += cost.quantity as i64;
+        self.raw_material_cost += cost.raw_material_cost;
+        self.production_cost += cost.production_cost;
+        self.storage_cost += cost.storage_cost;
+        self.penalty += cost.penalty;
+        self.total += cost.total;
+    }
+}
+
+impl CostReport {
+    /// Costs of every delivered order, with totals per client and per piece.
+    pub async fn delivered(con: &mut PgConnection) -> sqlx::Result<Self> {
+        let orders = OrderCost::get_delivered(con).await?;
+        Ok(Self::new(orders))
+    }
+
+    fn new(orders: Vec<OrderCost>) -> Self {
+        let mut totals = CostTotals::default();
+        let mut by_client = BTreeMap::<String, CostTotals>::new();
+        let mut by_piece = BTreeMap::<String, (FinalPiece, CostTotals)>::new();
+        for cost in &orders {
+            totals.add(cost);
+            by_client
+                .entry(cost.client_name.clone())
+                .or_default()
+                .add(cost);
+            by_piece
+                .entry(cost.piece.to_string())
+                .or_insert((cost.piece, CostTotals::default()))
+                .1
+                .add(cost);
+        }
+
+        Self {
+            orders,
+            by_client: by_client
+                .into_iter()
+                .map(|(client_name, totals)| ClientCosts {
+                    client_name,
+                    totals,
+                })
+                .collect(),
+            by_piece: by_piece
+                .into_values()
+                .map(|(piece, totals)| PieceCosts { piece, totals })
+                .collect(),
+            totals,
+        }
+    }
+}
+
+/// Profit of a delivered order with a known selling price, in cents.
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderProfit {
+    pub order_id: Uuid,
+    pub client_name: String,
+    pub piece: FinalPiece,
+    pub quantity: i32,
+    pub delivery_day: i32,
+    pub revenue: i64,
+    pub cost: i64,
+    pub profit: i64,
+    /// profit over revenue, unknown when nothing was charged
+    pub margin: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct ProfitTotals {
+    pub orders: i64,
+    pub revenue: i64,
+    pub cost: i64,
+    pub profit: i64,
+    pub margin: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientProfits {
+    pub client_name: String,
+    #[serde(flatten)]
+    pub totals: ProfitTotals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DayProfits {
+    pub day: i32,
+    #[serde(flatten)]
+    pub totals: ProfitTotals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfitReport {
+    pub orders: Vec<OrderProfit>,
+    pub by_client: Vec<ClientProfits>,
+    pub by_day: Vec<DayProfits>,
+    pub totals: ProfitTotals,
+}
+
+fn margin(profit: i64, revenue: i64) -> Option<f64> {
+    match revenue {
+        0 => None,
+        _ => Some(profit as f64 / revenue as f64),
+    }
+}
+
+impl OrderProfit {
+    pub async fn get_delivered(
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                o.id as order_id,
+                c.name as client_name,
+                o.piece as "piece: FinalPiece",
+                o.quantity,
+                o.delivery_day as "delivery_day!",
+                (p.revenue::numeric * 100)::bigint as "revenue!",
+                (p.cost::numeric * 100)::bigint as "cost!",
+                (p.profit::numeric * 100)::bigint as "profit!"
+            FROM order_profits AS p
+            JOIN orders AS o ON o.id = p.order_id
+            JOIN clients AS c ON c.id = o.client_id
+            ORDER BY o.delivery_day, c.name, o.number
+            "#
+        )
+        .fetch_all(con)
+        .await?
+        .into_iter()
+        .map(|row| OrderProfit {
+            order_id: row.order_id,
+            client_name: row.client_name,
+            piece: row.piece,
+            quantity: row.quantity,
+            delivery_day: row.delivery_day,
+            revenue: row.revenue,
+            cost: row.cost,
+            profit: row.profit,
+            margin: margin(row.profit, row.revenue),
+        })
+        .collect())
+    }
+}
+
+impl ProfitTotals {
+    fn add(&mut self, profit: &OrderProfit) {
+        self.orders += 1;
+        self.revenue += profit.revenue;
+        self.cost += profit.cost;
+        self.profit += profit.profit;
+        self.margin = margin(self.profit, self.revenue);
+    }
+}
+
+impl ProfitReport {
+    /// Profit of every delivered order with a selling price, with totals
+    /// per client and per delivery day.
+    pub async fn delivered(con: &mut PgConnection) -> sqlx::Result<Self> {
+        let orders = OrderProfit::get_delivered(con).await?;
+        Ok(Self::new(orders))
+    }
+
+    fn new(orders: Vec<OrderProfit>) -> Self {
+        let mut totals = ProfitTotals::default();
+        let mut by_client = BTreeMap::<String, ProfitTotals>::new();
+        let mut by_day = BTreeMap::<i32, ProfitTotals>::new();
+        for profit in &orders {
+            totals.add(profit);
+            by_client
+                .entry(profit.client_name.clone())
+                .or_default()
+                .add(profit);
+            by_day.entry(profit.delivery_day).or_default().add(profit);
+        }
+
+        Self {
+            orders,
+            by_client: by_client
+                .into_iter()
+                .map(|(client_name, totals)| ClientProfits {
+                    client_name,
+                    totals,
+                })
+                .collect(),
+            by_day: by_day
+                .into_iter()
+                .map(|(day, totals)| DayProfits { day, totals })
+                .collect(),
+            totals,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::types::PgMoney;
+
+    use super::*;
+    use crate::{
+        configuration::get_configuration,
+        db_api::{
+            ClientOrder, Ingestion, Item, MaterialShipment, PieceKind,
+            Shipment, Transformation,
+        },
+    };
+
+    fn cost(client: &str, piece: FinalPiece, total: i64) -> OrderCost {
+        OrderCost {
+            order_id: Uuid::new_v4(),
+            client_name: client.to_string(),
+            piece,
+            quantity: 2,
+            status: OrderStatus::Delivered,
+            raw_material_cost: total / 2,
+            production_cost: total / 2,
+            storage_cost: 0,
+            penalty: 0,
+            total,
+        }
+    }
+
+    #[test]
+    fn aggregates_by_client_and_piece() {
+        let report = CostReport::new(vec![
+            cost("Client BB", FinalPiece::P5, 100),
+            cost("Client AA", FinalPiece::P6, 40),
+            cost("Client BB", FinalPiece::P6, 60),
+        ]);
+
+        assert_eq!(report.totals.orders, 3);
+        assert_eq!(report.totals.pieces, 6);
+        assert_eq!(report.totals.total, 200);
+
+        let clients = report
+            .by_client
+            .iter()
+            .map(|c| (c.client_name.as_str(), c.totals.total))
+            .collect::<Vec<_>>();
+        assert_eq!(clients, vec![("Client AA", 40), ("Client BB", 160)]);
+
+        let pieces = report
+            .by_piece
+            .iter()
+            .map(|p| (p.piece, p.totals.raw_material_cost))
+            .collect::<Vec<_>>();
+        assert_eq!(pieces, vec![(FinalPiece::P5, 50), (FinalPiece::P6, 50)]);
+    }
+
+    #[tokio::test]
+    async fn final_cost_charges_a_share_of_the_shipment() {
+        let pool = get_configuration()
+            .expect("Failed to read configuration")
+            .database
+            .create_test_db()
+            .await;
+
+        let order = ClientOrder::new(
+            "Client".to_string(),
+            1,
+            FinalPiece::P5,
+            1,
+            30,
+            0,
+            0,
+        );
+        let Ok(Ingestion::Inserted(order_id)) = order.insert_to_db(&pool).await
+        else {
+            panic!("Failed to insert order");
+        };
+
+        let mut con = pool.acquire().await.expect("Failed to acquire");
+        let material = Item::new(PieceKind::P1).set_order(Some(order_id));
+        material
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert item");
+        let shipment = Shipment::new(1, 0, 4, PgMoney(12000))
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert shipment");
+        MaterialShipment::new(material.id(), shipment)
+            .insert(&mut con)
+            .await
+            .expect("Failed to link shipment");
+        Shipment::arrived(shipment, 2, &mut con)
+            .await
+            .expect("Failed to record arrival");
+
+        let product = Item::new(PieceKind::P5).set_order(Some(order_id));
+        product
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert item");
+        Transformation::new(product.id(), material.id(), 1)
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert transformation");
+
+        // delivered on arrival, nothing is charged for storage
+        for status in ["completed", "delivered"] {
+            sqlx::query(
+                "UPDATE orders
+                SET status = $1::order_status, delivery_day = 2
+                WHERE id = $2",
+            )
+            .bind(status)
+            .bind(order_id)
+            .execute(&mut *con)
+            .await
+            .expect("Failed to deliver order");
+        }
+
+        let cost: PgMoney = sqlx::query_scalar(
+            "SELECT cost FROM order_final_costs WHERE order_id = $1",
+        )
+        .bind(order_id)
+        .fetch_one(&mut *con)
+        .await
+        .expect("Missing final cost");
+        // a quarter of the shipment, it used to be charged in full
+        assert_eq!(cost, PgMoney(3000));
+    }
+}
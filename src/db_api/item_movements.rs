@@ -0,0 +1,55 @@
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use super::ItemStatus;
+
+/// Change of status or location of an item, recorded by the database every
+/// time an item is written.
+#[derive(Debug, Serialize)]
+pub struct ItemMovement {
+    pub day: i32,
+    /// route or task that moved the item
+    pub actor: Option<String>,
+    pub previous_status: Option<ItemStatus>,
+    pub status: ItemStatus,
+    pub previous_location: Option<String>,
+    pub location: Option<String>,
+}
+
+impl ItemMovement {
+    /// Names the route or task behind the movements of the current
+    /// transaction, outside of one it has no effect.
+    pub async fn set_actor(
+        actor: &str,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!("SELECT set_config('app.actor', $1, true)", actor)
+            .fetch_one(con)
+            .await?;
+        Ok(())
+    }
+
+    /// Movements of an item, oldest first.
+    pub async fn get_by_item(
+        item_id: Uuid,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            ItemMovement,
+            r#"SELECT
+                day,
+                actor,
+                previous_status as "previous_status: ItemStatus",
+                status as "status: ItemStatus",
+                previous_location,
+                location
+            FROM item_movements
+            WHERE item_id = $1
+            ORDER BY id"#,
+            item_id
+        )
+        .fetch_all(con)
+        .await
+    }
+}
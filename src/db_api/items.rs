@@ -1,10 +1,19 @@
-use sqlx::postgres::{types::PgMoney, PgQueryResult};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    postgres::{types::PgMoney, PgQueryResult},
+    PgConnection,
+};
 use uuid::Uuid;
 
+use crate::error::{Error, Result};
+
 use super::PieceKind;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type,
+)]
 #[sqlx(type_name = "item_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum ItemStatus {
     Pending,
     InStock,
@@ -25,12 +34,70 @@ impl std::fmt::Display for ItemStatus {
     }
 }
 
+/// Where an item is, one of the codes of the `warehouses` or of the
+/// `production_lines` tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Warehouse(String),
+    ProductionLine(String),
+}
+
+impl Location {
+    /// Looks `code` up in the warehouses and production lines, unknown codes
+    /// are invalid.
+    pub async fn get(code: &str, con: &mut PgConnection) -> Result<Self> {
+        let row = sqlx::query!(
+            r#"
+            SELECT code as "code!", true as "is_warehouse!"
+            FROM warehouses WHERE code = $1
+            UNION ALL
+            SELECT code, false
+            FROM production_lines WHERE code = $1
+            "#,
+            code
+        )
+        .fetch_optional(con)
+        .await?;
+
+        match row {
+            Some(row) => Ok(Self::new(row.code, row.is_warehouse)),
+            None => {
+                Err(Error::Invalid(format!("Location {} does not exist", code)))
+            }
+        }
+    }
+
+    fn new(code: String, is_warehouse: bool) -> Self {
+        match is_warehouse {
+            true => Location::Warehouse(code),
+            false => Location::ProductionLine(code),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            Location::Warehouse(code) | Location::ProductionLine(code) => code,
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Location::Warehouse(code) => write!(f, "warehouse {}", code),
+            Location::ProductionLine(code) => {
+                write!(f, "production line {}", code)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Item {
     id: Uuid,
     piece_kind: PieceKind,
     order_id: Option<Uuid>,
-    location: Option<String>,
+    location: Option<Location>,
     status: ItemStatus,
     acc_cost: PgMoney,
 }
@@ -52,30 +119,32 @@ impl Item {
         self
     }
 
-    pub fn produce(
-        mut self,
-        cost: PgMoney,
-        line: impl ToString,
-    ) -> anyhow::Result<Self> {
+    pub fn produce(mut self, cost: PgMoney, line: Location) -> Result<Self> {
+        if !matches!(line, Location::ProductionLine(_)) {
+            return Err(Error::Invalid(format!(
+                "Item {} cannot be produced in {}",
+                self.id, line
+            )));
+        }
         if self.status != ItemStatus::Pending {
-            anyhow::bail!(format!(
+            return Err(Error::Conflict(format!(
                 "Item {} is {}, cannot produce",
                 self.id, self.status
-            ));
+            )));
         }
 
         self.status = ItemStatus::InTransit;
-        self.location = Some(line.to_string());
+        self.location = Some(line);
         self.acc_cost = cost;
         Ok(self)
     }
 
-    pub fn consume(mut self) -> anyhow::Result<Self> {
+    pub fn consume(mut self) -> Result<Self> {
         if self.status != ItemStatus::InTransit {
-            anyhow::bail!(format!(
+            return Err(Error::Conflict(format!(
                 "Item {} is {}, cannot consume",
                 self.id, self.status
-            ));
+            )));
         }
 
         self.status = ItemStatus::Consumed;
@@ -83,35 +152,41 @@ impl Item {
         Ok(self)
     }
 
-    pub fn enter_warehouse(
-        mut self,
-        warehouse: impl ToString,
-    ) -> anyhow::Result<Self> {
+    pub fn enter_warehouse(mut self, warehouse: Location) -> Result<Self> {
+        if !matches!(warehouse, Location::Warehouse(_)) {
+            return Err(Error::Invalid(format!(
+                "Item {} cannot enter {}, it is not a warehouse",
+                self.id, warehouse
+            )));
+        }
         if self.status != ItemStatus::InTransit {
-            anyhow::bail!(format!(
+            return Err(Error::Conflict(format!(
                 "Item {} is {}, cannot enter warehouse",
                 self.id, self.status
-            ));
+            )));
         }
 
         self.status = ItemStatus::InStock;
-        self.location = Some(warehouse.to_string());
+        self.location = Some(warehouse);
         Ok(self)
     }
 
-    pub fn exit_warehouse(
-        mut self,
-        production_line: impl ToString,
-    ) -> anyhow::Result<Self> {
+    pub fn exit_warehouse(mut self, production_line: Location) -> Result<Self> {
+        if !matches!(production_line, Location::ProductionLine(_)) {
+            return Err(Error::Invalid(format!(
+                "Item {} cannot exit to {}, it is not a production line",
+                self.id, production_line
+            )));
+        }
         if self.status != ItemStatus::InStock {
-            anyhow::bail!(format!(
+            return Err(Error::Conflict(format!(
                 "Item {} is {}, cannot exit warehouse",
                 self.id, self.status
-            ));
+            )));
         }
 
         self.status = ItemStatus::InTransit;
-        self.location = Some(production_line.to_string());
+        self.location = Some(production_line);
         Ok(self)
     }
 
@@ -130,7 +205,7 @@ impl Item {
             self.id,
             self.piece_kind as PieceKind,
             self.order_id,
-            self.location,
+            self.location.as_ref().map(Location::code),
             self.status as ItemStatus,
             self.acc_cost
         )
@@ -141,21 +216,35 @@ impl Item {
     pub async fn get_by_id(
         id: Uuid,
         con: &mut sqlx::PgConnection,
-    ) -> sqlx::Result<Self> {
-        sqlx::query_as!(
-            Item,
+    ) -> Result<Self> {
+        let row = sqlx::query!(
             r#"SELECT
                 id,
                 piece_kind as "piece_kind: PieceKind",
                 order_id,
                 location,
+                EXISTS (
+                    SELECT 1 FROM warehouses WHERE code = items.location
+                ) as "in_warehouse!",
                 status as "status: ItemStatus",
                 acc_cost
             FROM items WHERE id = $1"#,
             id
         )
-        .fetch_one(con)
-        .await
+        .fetch_optional(con)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Item {}", id)))?;
+
+        Ok(Item {
+            id: row.id,
+            piece_kind: row.piece_kind,
+            order_id: row.order_id,
+            location: row
+                .location
+                .map(|code| Location::new(code, row.in_warehouse)),
+            status: row.status,
+            acc_cost: row.acc_cost,
+        })
     }
 
     pub async fn update(
@@ -171,7 +260,7 @@ impl Item {
                 acc_cost = $4
             WHERE id = $5"#,
             self.order_id,
-            self.location,
+            self.location.as_ref().map(Location::code),
             self.status as ItemStatus,
             self.acc_cost,
             self.id
@@ -182,6 +271,32 @@ impl Item {
         Ok(())
     }
 
+    /// Detaches from an order its items in stock, which become free stock,
+    /// and its raw materials that did not arrive yet.
+    ///
+    /// Raw materials that did not arrive should first be taken out of their
+    /// shipments, they then stay pending outside of any order and are never
+    /// bought. Other items of the order are left to it.
+    pub async fn release_by_order(
+        order_id: Uuid,
+        con: &mut sqlx::PgConnection,
+    ) -> sqlx::Result<u64> {
+        Ok(sqlx::query!(
+            r#"UPDATE items AS i
+            SET order_id = NULL
+            FROM pieces AS p
+            WHERE p.code = i.piece_kind
+                AND i.order_id = $1
+                AND (i.status = $2 OR (i.status = $3 AND p.category = 'raw'))"#,
+            order_id,
+            ItemStatus::InStock as ItemStatus,
+            ItemStatus::Pending as ItemStatus,
+        )
+        .execute(con)
+        .await?
+        .rows_affected())
+    }
+
     pub fn id(&self) -> Uuid {
         self.id
     }
@@ -190,3 +305,150 @@ impl Item {
         self.order_id
     }
 }
+
+/// Read-only view of an item, as exposed by the API. Costs are in cents.
+#[derive(Debug, Serialize)]
+pub struct ItemDetails {
+    pub id: Uuid,
+    pub piece_kind: PieceKind,
+    pub order_id: Option<Uuid>,
+    pub location: Option<String>,
+    pub status: ItemStatus,
+    pub acc_cost: i64,
+}
+
+/// Filters of the inventory, every field left out matches all items.
+#[derive(Debug, Default, Deserialize)]
+pub struct ItemFilter {
+    pub piece_kind: Option<PieceKind>,
+    pub status: Option<ItemStatus>,
+    pub location: Option<String>,
+    /// only items allocated to an order, or only free stock
+    pub allocated: Option<bool>,
+}
+
+impl ItemDetails {
+    pub async fn get_by_id(id: Uuid, con: &mut PgConnection) -> Result<Self> {
+        sqlx::query_as!(
+            ItemDetails,
+            r#"SELECT
+                id,
+                piece_kind as "piece_kind: PieceKind",
+                order_id,
+                location,
+                status as "status: ItemStatus",
+                (acc_cost::numeric * 100)::bigint as "acc_cost!"
+            FROM items
+            WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(con)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Item {}", id)))
+    }
+
+    pub async fn get_filtered(
+        filter: &ItemFilter,
+        limit: i64,
+        offset: i64,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            ItemDetails,
+            r#"SELECT
+                id,
+                piece_kind as "piece_kind: PieceKind",
+                order_id,
+                location,
+                status as "status: ItemStatus",
+                (acc_cost::numeric * 100)::bigint as "acc_cost!"
+            FROM items
+            WHERE ($1::piece_kind IS NULL OR piece_kind = $1)
+                AND ($2::item_status IS NULL OR status = $2)
+                AND ($3::text IS NULL OR location = $3)
+                AND ($4::bool IS NULL OR (order_id IS NOT NULL) = $4)
+            ORDER BY location, piece_kind, status, id
+            LIMIT $5 OFFSET $6"#,
+            filter.piece_kind as Option<PieceKind>,
+            filter.status as Option<ItemStatus>,
+            filter.location,
+            filter.allocated,
+            limit,
+            offset,
+        )
+        .fetch_all(con)
+        .await
+    }
+
+    pub async fn count_filtered(
+        filter: &ItemFilter,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<i64> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "count!"
+            FROM items
+            WHERE ($1::piece_kind IS NULL OR piece_kind = $1)
+                AND ($2::item_status IS NULL OR status = $2)
+                AND ($3::text IS NULL OR location = $3)
+                AND ($4::bool IS NULL OR (order_id IS NOT NULL) = $4)"#,
+            filter.piece_kind as Option<PieceKind>,
+            filter.status as Option<ItemStatus>,
+            filter.location,
+            filter.allocated,
+        )
+        .fetch_one(con)
+        .await
+    }
+
+    pub async fn get_by_order(
+        order_id: Uuid,
+        con: &mut sqlx::PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            ItemDetails,
+            r#"SELECT
+                id,
+                piece_kind as "piece_kind: PieceKind",
+                order_id,
+                location,
+                status as "status: ItemStatus",
+                (acc_cost::numeric * 100)::bigint as "acc_cost!"
+            FROM items
+            WHERE order_id = $1
+            ORDER BY piece_kind, id"#,
+            order_id
+        )
+        .fetch_all(con)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions_check_the_kind_of_location() {
+        let line = Location::ProductionLine("L1".to_string());
+        let warehouse = Location::Warehouse("W1".to_string());
+
+        let item = Item::new(PieceKind::P5);
+        assert!(matches!(
+            item.clone().produce(PgMoney(0), warehouse.clone()),
+            Err(Error::Invalid(_))
+        ));
+
+        let item = item.produce(PgMoney(0), line.clone()).unwrap();
+        assert!(matches!(
+            item.clone().enter_warehouse(line.clone()),
+            Err(Error::Invalid(_))
+        ));
+
+        let item = item.enter_warehouse(warehouse.clone()).unwrap();
+        assert_eq!(item.location, Some(warehouse.clone()));
+        assert!(matches!(
+            item.exit_warehouse(warehouse),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sqlx::PgConnection;
+
+use super::ToolType;
+
+#[derive(Debug, Clone)]
+pub struct Machine {
+    code: String,
+    day_capacity: i64,
+    tools: Vec<ToolType>,
+    changeovers: HashMap<(ToolType, ToolType), i64>,
+}
+
+impl Machine {
+    #[cfg(test)]
+    pub fn new(code: &str, day_capacity: i64, tools: Vec<ToolType>) -> Self {
+        Self {
+            code: code.to_string(),
+            day_capacity,
+            tools,
+            changeovers: HashMap::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_changeover(
+        mut self,
+        from: ToolType,
+        to: ToolType,
+        time: i64,
+    ) -> Self {
+        self.changeovers.insert((from, to), time);
+        self
+    }
+
+    /// Gives every machine `seconds` of production per simulation day.
+    pub async fn set_day_capacity(
+        seconds: i32,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!("UPDATE machines SET day_capacity = $1", seconds)
+            .execute(con)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_all(con: &mut PgConnection) -> sqlx::Result<Vec<Self>> {
+        let mut machines = sqlx::query!(
+            r#"
+            SELECT
+                m.code,
+                m.day_capacity,
+                mt.tool as "tool: ToolType"
+            FROM machines AS m
+            JOIN machine_tools AS mt ON mt.machine = m.code
+            ORDER BY m.code
+            "#
+        )
+        .fetch_all(&mut *con)
+        .await?
+        .into_iter()
+        .fold(BTreeMap::new(), |mut map, row| {
+            map.entry(row.code.clone())
+                .or_insert_with(|| Machine {
+                    code: row.code,
+                    day_capacity: row.day_capacity as i64,
+                    tools: Vec::new(),
+                    changeovers: HashMap::new(),
+                })
+                .tools
+                .push(row.tool);
+            map
+        });
+
+        let changeovers = sqlx::query!(
+            r#"
+            SELECT
+                machine,
+                from_tool as "from_tool: ToolType",
+                to_tool as "to_tool: ToolType",
+                changeover_time
+            FROM tool_changeovers
+            "#
+        )
+        .fetch_all(con)
+        .await?;
+
+        for row in changeovers {
+            if let Some(machine) = machines.get_mut(&row.machine) {
+                machine.changeovers.insert(
+                    (row.from_tool, row.to_tool),
+                    row.changeover_time as i64,
+                );
+            }
+        }
+
+        Ok(machines.into_values().collect())
+    }
+
+    pub fn can_use(&self, tool: ToolType) -> bool {
+        self.tools.contains(&tool)
+    }
+
+    /// Time needed to swap `from` for `to`, zero if they are the same tool.
+    pub fn changeover_time(&self, from: ToolType, to: ToolType) -> i64 {
+        if from == to {
+            return 0;
+        }
+        self.changeovers.get(&(from, to)).copied().unwrap_or(0)
+    }
+
+    /// Quickest changeover that mounts `tool` coming from any other tool
+    /// of this machine.
+    pub fn setup_time(&self, tool: ToolType) -> i64 {
+        self.tools
+            .iter()
+            .filter(|t| **t != tool)
+            .map(|t| self.changeover_time(*t, tool))
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Total changeover time of working through `tools` in the order that
+    /// needs the least changeover time, one tool after the other.
+    pub fn sequence_changeover(&self, tools: &[ToolType]) -> i64 {
+        fn best(
+            machine: &Machine,
+            current: ToolType,
+            remaining: &mut Vec<ToolType>,
+        ) -> i64 {
+            (0..remaining.len())
+                .map(|i| {
+                    let next = remaining.remove(i);
+                    let time = machine.changeover_time(current, next)
+                        + best(machine, next, remaining);
+                    remaining.insert(i, next);
+                    time
+                })
+                .min()
+                .unwrap_or(0)
+        }
+
+        let mut remaining = tools.to_vec();
+        (0..remaining.len())
+            .map(|i| {
+                let first = remaining.remove(i);
+                let time = best(self, first, &mut remaining);
+                remaining.insert(i, first);
+                time
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn day_capacity(&self) -> i64 {
+        self.day_capacity
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MachineLoad {
+    pub machine: String,
+    pub date: i32,
+    pub tool: ToolType,
+    pub booked_time: i64,
+}
+
+impl MachineLoad {
+    /// Production time already booked on each machine by pending
+    /// transformations, per tool, from `day` onwards.
+    pub async fn get_from_day(
+        day: i32,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                t.machine as "machine!",
+                t.date as "date!",
+                r.tool as "tool: ToolType",
+                SUM(r.operation_time) as "booked_time!"
+            FROM transformations AS t
+            JOIN recipes AS r ON t.recipe_id = r.id
+            WHERE t.status = 'pending'
+                AND t.machine IS NOT NULL
+                AND t.date >= $1
+            GROUP BY t.machine, t.date, r.tool
+            "#,
+            day
+        )
+        .fetch_all(con)
+        .await?
+        .into_iter()
+        .map(|row| MachineLoad {
+            machine: row.machine,
+            date: row.date,
+            tool: row.tool,
+            booked_time: row.booked_time,
+        })
+        .collect())
+    }
+}
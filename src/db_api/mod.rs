@@ -1,6 +1,9 @@
 // Modules
 mod clients;
+mod costs;
+mod item_movements;
 mod items;
+mod machines;
 mod orders;
 mod pieces;
 mod recipes;
@@ -8,10 +11,14 @@ mod shipments;
 mod statistics;
 mod suppliers;
 mod transformations;
+mod warehouses;
 
 // Re-exports
 pub use clients::*;
+pub use costs::*;
+pub use item_movements::*;
 pub use items::*;
+pub use machines::*;
 pub use orders::*;
 pub use pieces::*;
 pub use recipes::*;
@@ -19,6 +26,7 @@ pub use shipments::*;
 pub use statistics::*;
 pub use suppliers::*;
 pub use transformations::*;
+pub use warehouses::*;
 
 use sqlx::PgConnection;
 
@@ -30,6 +38,21 @@ pub async fn get_date(con: &mut PgConnection) -> sqlx::Result<u32> {
     )
 }
 
+/// Share of the raw material cost charged for each day it is stored, used by
+/// the cost views.
+pub async fn set_holding_cost_rate(
+    rate: f64,
+    con: &mut PgConnection,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "UPDATE cost_settings SET holding_cost_rate = $1::float8",
+        rate
+    )
+    .execute(con)
+    .await?;
+    Ok(())
+}
+
 pub async fn update_date(
     new_date: u32,
     con: &mut PgConnection,
@@ -47,11 +70,13 @@ pub async fn update_date(
 pub enum NotificationChannel {
     NewOrder,
     MaterialsNeeded,
+    RecipesChanged,
 }
 
 impl NotificationChannel {
     const NEW_ORDER_CHANNEL: &'static str = "new_order";
     const MATERIALS_NEEDED_CHANNEL: &'static str = "materials_needed";
+    const RECIPES_CHANGED_CHANNEL: &'static str = "recipes_changed";
 
     pub async fn notify(
         channel: NotificationChannel,
@@ -70,6 +95,7 @@ impl std::fmt::Display for NotificationChannel {
         match self {
             Nc::NewOrder => write!(f, "new_order"),
             Nc::MaterialsNeeded => write!(f, "materials_needed"),
+            Nc::RecipesChanged => write!(f, "recipes_changed"),
         }
     }
 }
@@ -85,6 +111,9 @@ impl TryFrom<&str> for NotificationChannel {
             NotificationChannel::MATERIALS_NEEDED_CHANNEL => {
                 Ok(NotificationChannel::MaterialsNeeded)
             }
+            NotificationChannel::RECIPES_CHANGED_CHANNEL => {
+                Ok(NotificationChannel::RecipesChanged)
+            }
             _ => Err(anyhow::anyhow!("Invalid channel name")),
         }
     }
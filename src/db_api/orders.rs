@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use sqlx::{
     postgres::{types::PgMoney, PgQueryResult},
     query,
@@ -5,10 +6,13 @@ use sqlx::{
     PgConnection,
 };
 
+use crate::error::{Error, Result};
+
 use super::{pieces::FinalPiece, PieceKind};
 
-#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "order_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum OrderStatus {
     Pending,
     Scheduled,
@@ -46,9 +50,12 @@ pub struct Order {
     status: OrderStatus,
     placement_day: i32,
     delivery_day: Option<i32>,
+    cancellation_reason: Option<String>,
+    unit_price: Option<PgMoney>,
+    flags: Vec<String>,
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, Serialize)]
 pub struct Delivery {
     id: Uuid,
     piece: FinalPiece,
@@ -77,9 +84,25 @@ impl Order {
             status: OrderStatus::Pending,
             placement_day: 0,
             delivery_day: None,
+            cancellation_reason: None,
+            unit_price: None,
+            flags: Vec::new(),
         }
     }
 
+    pub fn with_unit_price(mut self, unit_price: Option<i64>) -> Self {
+        self.unit_price = unit_price.map(PgMoney);
+        self
+    }
+
+    /// Codes of the validation rules the order breaks.
+    pub fn with_flags(mut self, flags: Vec<String>) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Does nothing if the client already has an order with the same
+    /// number, in which case no rows are affected.
     pub async fn insert_to_db(
         order: &Order,
         con: &mut PgConnection,
@@ -96,9 +119,12 @@ impl Order {
                 due_date,
                 early_penalty,
                 late_penalty,
-                placement_day
+                placement_day,
+                unit_price,
+                flags
             )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (client_id, number) DO NOTHING
             "#,
             order.id,
             order.client_id,
@@ -109,23 +135,97 @@ impl Order {
             order.early_penalty,
             order.late_penalty,
             placement_day as i32,
+            order.unit_price,
+            &order.flags,
         )
         .execute(con)
         .await
     }
 
-    pub async fn get_by_id(
-        id: Uuid,
-        con: &mut PgConnection,
-    ) -> sqlx::Result<Order> {
+    pub async fn get_by_id(id: Uuid, con: &mut PgConnection) -> Result<Order> {
         //NOTE: the query_as macro dislikes the use of the * wildcard
         //      due to the custom enum types.
         //      So i'm using the query_as function in this scenario.
         //      instead of the macro query_as!
         sqlx::query_as(r#"SELECT * FROM orders WHERE id = $1"#)
             .bind(id)
-            .fetch_one(con)
-            .await
+            .fetch_optional(con)
+            .await?
+            .ok_or_else(|| Error::not_found(format!("Order {}", id)))
+    }
+
+    pub async fn find_by_number(
+        client_id: Uuid,
+        number: i32,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Option<Order>> {
+        sqlx::query_as(
+            r#"SELECT * FROM orders WHERE client_id = $1 AND number = $2"#,
+        )
+        .bind(client_id)
+        .bind(number)
+        .fetch_optional(con)
+        .await
+    }
+
+    /// Whether both orders ask for the same thing, regardless of their id,
+    /// their flags and of what happened to them since they were placed.
+    pub fn same_request(&self, other: &Order) -> bool {
+        self.client_id == other.client_id
+            && self.number == other.number
+            && self.piece == other.piece
+            && self.quantity == other.quantity
+            && self.due_date == other.due_date
+            && self.early_penalty == other.early_penalty
+            && self.late_penalty == other.late_penalty
+            && self.unit_price == other.unit_price
+    }
+
+    /// Keeps `self` for review as a conflicting version of `existing`,
+    /// unless the same version was already kept.
+    pub async fn insert_conflict(
+        &self,
+        existing: Uuid,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        let received_day = super::get_date(con).await?;
+
+        query!(
+            r#"INSERT INTO order_conflicts (
+                order_id,
+                piece,
+                quantity,
+                due_date,
+                late_penalty,
+                early_penalty,
+                unit_price,
+                received_day
+            )
+            SELECT $1, $2, $3, $4, $5, $6, $7, $8
+            WHERE NOT EXISTS (
+                SELECT 1 FROM order_conflicts
+                WHERE order_id = $1
+                    AND piece = $2
+                    AND quantity = $3
+                    AND due_date = $4
+                    AND late_penalty = $5
+                    AND early_penalty = $6
+                    AND unit_price IS NOT DISTINCT FROM $7
+            )
+            "#,
+            existing,
+            self.piece as FinalPiece,
+            self.quantity,
+            self.due_date,
+            self.late_penalty,
+            self.early_penalty,
+            self.unit_price,
+            received_day as i32,
+        )
+        .execute(con)
+        .await?;
+
+        Ok(())
     }
 
     pub async fn get_by_item_id(
@@ -162,6 +262,8 @@ impl Order {
         .await
     }
 
+    /// Only pending orders are scheduled, so no rows are affected if the
+    /// order was canceled in the meantime.
     pub async fn schedule(
         &self,
         delivery_day: i32,
@@ -171,10 +273,37 @@ impl Order {
             r#"UPDATE orders
             SET delivery_day = $1,
                 status = $2
-            WHERE id = $3"#,
+            WHERE id = $3 AND status = $4"#,
             delivery_day,
             OrderStatus::Scheduled as OrderStatus,
             self.id,
+            OrderStatus::Pending as OrderStatus,
+        )
+        .execute(con)
+        .await
+    }
+
+    pub fn can_cancel(&self) -> bool {
+        !matches!(self.status, OrderStatus::Delivered | OrderStatus::Canceled)
+    }
+
+    /// Marks the order as canceled, as long as its status did not change
+    /// since it was read.
+    pub async fn cancel(
+        &self,
+        reason: &str,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<PgQueryResult> {
+        query!(
+            r#"UPDATE orders
+            SET status = $1,
+                delivery_day = NULL,
+                cancellation_reason = $2
+            WHERE id = $3 AND status = $4"#,
+            OrderStatus::Canceled as OrderStatus,
+            reason,
+            self.id,
+            self.status as OrderStatus,
         )
         .execute(con)
         .await
@@ -203,8 +332,8 @@ impl Order {
         con: &mut PgConnection,
         order_id: Uuid,
         delivery_day: u32,
-    ) -> sqlx::Result<PgQueryResult> {
-        query!(
+    ) -> Result<()> {
+        let res = query!(
             r#"
             UPDATE orders
             SET status = $1,
@@ -216,7 +345,12 @@ impl Order {
             order_id,
         )
         .execute(con)
-        .await
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::not_found(format!("Order {}", order_id)));
+        }
+        Ok(())
     }
 
     pub fn piece(&self) -> PieceKind {
@@ -239,3 +373,201 @@ impl Order {
         self.status
     }
 }
+
+/// Read-only view of an order, as exposed by the API.
+///
+/// Money amounts are in cents.
+#[derive(Debug, Serialize)]
+pub struct OrderSummary {
+    pub id: Uuid,
+    pub client_id: Uuid,
+    pub client_name: String,
+    pub number: i32,
+    pub piece: FinalPiece,
+    pub quantity: i32,
+    pub due_date: i32,
+    pub early_penalty: i64,
+    pub late_penalty: i64,
+    pub unit_price: Option<i64>,
+    pub status: OrderStatus,
+    pub placement_day: i32,
+    pub delivery_day: Option<i32>,
+    pub cancellation_reason: Option<String>,
+    /// codes of the validation rules the order was accepted despite breaking
+    pub flags: Vec<String>,
+}
+
+/// Filters for listing orders, unset fields match every order.
+#[derive(Debug, Default, Deserialize)]
+pub struct OrderFilter {
+    /// client name
+    pub client: Option<String>,
+    pub status: Option<OrderStatus>,
+    pub piece: Option<FinalPiece>,
+    pub due_from: Option<i32>,
+    pub due_to: Option<i32>,
+    pub placement_day: Option<i32>,
+    /// only orders with (or without) validation flags
+    pub flagged: Option<bool>,
+}
+
+impl OrderSummary {
+    pub async fn get_by_id(
+        id: Uuid,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as!(
+            OrderSummary,
+            r#"
+            SELECT
+                o.id,
+                o.client_id,
+                c.name as client_name,
+                o.number,
+                o.piece as "piece: FinalPiece",
+                o.quantity,
+                o.due_date,
+                (o.early_penalty::numeric * 100)::bigint as "early_penalty!",
+                (o.late_penalty::numeric * 100)::bigint as "late_penalty!",
+                (o.unit_price::numeric * 100)::bigint as unit_price,
+                o.status as "status: OrderStatus",
+                o.placement_day,
+                o.delivery_day,
+                o.cancellation_reason,
+                o.flags
+            FROM orders AS o
+            JOIN clients AS c ON c.id = o.client_id
+            WHERE o.id = $1
+            "#,
+            id
+        )
+        .fetch_optional(con)
+        .await
+    }
+
+    /// Orders matching `filter`, oldest placement first.
+    pub async fn get_filtered(
+        filter: &OrderFilter,
+        limit: i64,
+        offset: i64,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            OrderSummary,
+            r#"
+            SELECT
+                o.id,
+                o.client_id,
+                c.name as client_name,
+                o.number,
+                o.piece as "piece: FinalPiece",
+                o.quantity,
+                o.due_date,
+                (o.early_penalty::numeric * 100)::bigint as "early_penalty!",
+                (o.late_penalty::numeric * 100)::bigint as "late_penalty!",
+                (o.unit_price::numeric * 100)::bigint as unit_price,
+                o.status as "status: OrderStatus",
+                o.placement_day,
+                o.delivery_day,
+                o.cancellation_reason,
+                o.flags
+            FROM orders AS o
+            JOIN clients AS c ON c.id = o.client_id
+            WHERE ($1::text IS NULL OR c.name = $1)
+                AND ($2::order_status IS NULL OR o.status = $2)
+                AND ($3::piece_kind IS NULL OR o.piece = $3)
+                AND ($4::int IS NULL OR o.due_date >= $4)
+                AND ($5::int IS NULL OR o.due_date <= $5)
+                AND ($6::int IS NULL OR o.placement_day = $6)
+                AND ($7::bool IS NULL OR (cardinality(o.flags) > 0) = $7)
+            ORDER BY o.placement_day, c.name, o.number
+            LIMIT $8 OFFSET $9
+            "#,
+            filter.client,
+            filter.status as Option<OrderStatus>,
+            filter.piece as Option<FinalPiece>,
+            filter.due_from,
+            filter.due_to,
+            filter.placement_day,
+            filter.flagged,
+            limit,
+            offset,
+        )
+        .fetch_all(con)
+        .await
+    }
+
+    pub async fn count_filtered(
+        filter: &OrderFilter,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<i64> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!"
+            FROM orders AS o
+            JOIN clients AS c ON c.id = o.client_id
+            WHERE ($1::text IS NULL OR c.name = $1)
+                AND ($2::order_status IS NULL OR o.status = $2)
+                AND ($3::piece_kind IS NULL OR o.piece = $3)
+                AND ($4::int IS NULL OR o.due_date >= $4)
+                AND ($5::int IS NULL OR o.due_date <= $5)
+                AND ($6::int IS NULL OR o.placement_day = $6)
+                AND ($7::bool IS NULL OR (cardinality(o.flags) > 0) = $7)
+            "#,
+            filter.client,
+            filter.status as Option<OrderStatus>,
+            filter.piece as Option<FinalPiece>,
+            filter.due_from,
+            filter.due_to,
+            filter.placement_day,
+            filter.flagged,
+        )
+        .fetch_one(con)
+        .await
+    }
+}
+
+/// Order received with the number of an existing order but a different
+/// content. Money amounts are in cents.
+#[derive(Debug, Serialize)]
+pub struct OrderConflict {
+    pub id: i64,
+    pub order_id: Uuid,
+    pub client_name: String,
+    pub number: i32,
+    pub piece: FinalPiece,
+    pub quantity: i32,
+    pub due_date: i32,
+    pub late_penalty: i64,
+    pub early_penalty: i64,
+    pub unit_price: Option<i64>,
+    pub received_day: i32,
+}
+
+impl OrderConflict {
+    pub async fn get_all(con: &mut PgConnection) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            OrderConflict,
+            r#"
+            SELECT
+                oc.id,
+                oc.order_id,
+                c.name as client_name,
+                o.number,
+                oc.piece as "piece: FinalPiece",
+                oc.quantity,
+                oc.due_date,
+                (oc.late_penalty::numeric * 100)::bigint as "late_penalty!",
+                (oc.early_penalty::numeric * 100)::bigint as "early_penalty!",
+                (oc.unit_price::numeric * 100)::bigint as unit_price,
+                oc.received_day
+            FROM order_conflicts AS oc
+            JOIN orders AS o ON o.id = oc.order_id
+            JOIN clients AS c ON c.id = o.client_id
+            ORDER BY oc.id
+            "#
+        )
+        .fetch_all(con)
+        .await
+    }
+}
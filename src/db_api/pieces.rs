@@ -8,7 +8,11 @@ use uuid::Uuid;
 
 use super::ItemStatus;
 
-#[subenum(FinalPiece, InterPiece, RawMaterial(derive(Sequence)))]
+#[subenum(
+    FinalPiece(derive(Sequence)),
+    InterPiece,
+    RawMaterial(derive(Sequence))
+)]
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type,
 )]
@@ -61,7 +65,6 @@ impl TryFrom<&str> for FinalPiece {
 #[derive(Debug, Clone)]
 pub struct RawMaterialDetails {
     pub item_id: Uuid,
-    pub order_id: Uuid,
     pub due_date: i32,
 }
 
@@ -79,6 +82,7 @@ impl RawMaterial {
             WHERE items.status = $1
                 AND items.piece_kind = $2
                 AND transformations.date IS NOT NULL
+                AND transformations.status = 'pending'
                 AND raw_material_shipments.raw_material_id IS NULL
             GROUP BY transformations.date
             "#,
@@ -105,7 +109,6 @@ impl RawMaterial {
             r#"
             SELECT
                 items.id as item_id,
-                items.order_id as order_id,
                 transformations.date as due_date
             FROM items
             JOIN transformations ON items.id = transformations.material_id
@@ -114,6 +117,7 @@ impl RawMaterial {
                 AND items.piece_kind = $2
                 AND items.order_id IS NOT NULL
                 AND transformations.date IS NOT NULL
+                AND transformations.status = 'pending'
                 AND raw_material_shipments.raw_material_id IS NULL  -- Exclude shiped items
             ORDER BY transformations.date
             "#,
@@ -125,7 +129,6 @@ impl RawMaterial {
         .iter()
         .map(|row| RawMaterialDetails {
             item_id: row.item_id,
-            order_id: row.order_id.expect("selecting only non null"),
             due_date: row.due_date.expect("selecting only non null"),
         })
         .collect())
@@ -1,9 +1,11 @@
-use serde::Serialize;
-use sqlx::PgPool;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
 
 use super::PieceKind;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type,
+)]
 #[sqlx(type_name = "tool_type")]
 pub enum ToolType {
     T1,
@@ -20,14 +22,23 @@ pub struct Recipe {
     pub material_kind: PieceKind,
     pub product_kind: PieceKind,
     pub tool: ToolType,
-    pub operation_time: i64,
+    pub operation_time: i32,
+}
+
+impl ToolType {
+    pub async fn exists(&self, con: &mut PgConnection) -> sqlx::Result<bool> {
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM tools WHERE code = $1) as "exists!""#,
+            *self as ToolType,
+        )
+        .fetch_one(con)
+        .await
+    }
 }
 
 impl Recipe {
-    pub async fn get_by_product(
-        product: PieceKind,
-        pool: &PgPool,
-    ) -> sqlx::Result<Vec<Recipe>> {
+    /// Recipes currently in use, retired ones are left out.
+    pub async fn get_all(con: &mut PgConnection) -> sqlx::Result<Vec<Recipe>> {
         sqlx::query_as!(
             Recipe,
             r#"SELECT
@@ -36,10 +47,53 @@ impl Recipe {
                 product_kind as "product_kind: PieceKind",
                 tool as "tool: ToolType",
                 operation_time
-            FROM recipes WHERE product_kind = $1"#,
-            product as PieceKind,
+            FROM recipes
+            WHERE active
+            ORDER BY id"#,
         )
-        .fetch_all(pool)
+        .fetch_all(con)
         .await
     }
+
+    /// Blocks concurrent recipe edits until the end of the transaction, so
+    /// that each edit is validated against an up to date recipe set.
+    pub async fn lock(con: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!("LOCK TABLE recipes IN SHARE ROW EXCLUSIVE MODE")
+            .execute(con)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn insert(&self, con: &mut PgConnection) -> sqlx::Result<i64> {
+        Ok(sqlx::query!(
+            r#"
+            INSERT INTO recipes
+                (material_kind, product_kind, tool, operation_time)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+            self.material_kind as PieceKind,
+            self.product_kind as PieceKind,
+            self.tool as ToolType,
+            self.operation_time,
+        )
+        .fetch_one(con)
+        .await?
+        .id)
+    }
+
+    /// Takes a recipe out of use while keeping it around for the
+    /// transformations that were planned with it.
+    ///
+    /// Returns false if no recipe in use has the given id.
+    pub async fn retire(id: i64, con: &mut PgConnection) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE recipes SET active = false WHERE id = $1 AND active",
+            id
+        )
+        .execute(con)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
 }
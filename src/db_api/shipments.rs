@@ -1,6 +1,8 @@
-use sqlx::{postgres::types::PgMoney, PgConnection, PgPool};
+use sqlx::{postgres::types::PgMoney, PgConnection};
 use uuid::Uuid;
 
+use crate::error::{Error, Result};
+
 use super::RawMaterial;
 
 #[derive(Debug)]
@@ -51,8 +53,12 @@ impl Shipment {
         Ok(())
     }
 
-    pub async fn arrived(id: i64, date: i32, con: &PgPool) -> sqlx::Result<()> {
-        sqlx::query!(
+    pub async fn arrived(
+        id: i64,
+        date: i32,
+        con: &mut PgConnection,
+    ) -> Result<()> {
+        let res = sqlx::query!(
             r#"
             UPDATE shipments
             SET arrival_date = $1
@@ -64,6 +70,9 @@ impl Shipment {
         .execute(con)
         .await?;
 
+        if res.rows_affected() == 0 {
+            return Err(Error::not_found(format!("Shipment {}", id)));
+        }
         Ok(())
     }
 
@@ -145,6 +154,10 @@ impl Shipment {
     pub fn cost(&self) -> PgMoney {
         self.cost
     }
+
+    pub fn quantity(&self) -> i32 {
+        self.quantity
+    }
 }
 
 pub struct MaterialShipment {
@@ -182,6 +195,28 @@ impl MaterialShipment {
         Ok(())
     }
 
+    /// Unlinks the order's raw materials from shipments that did not arrive
+    /// yet, leaving room in those shipments for other orders.
+    pub async fn delete_pending_by_order(
+        order_id: Uuid,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<u64> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM raw_material_shipments AS rs
+            USING items AS i, shipments AS s
+            WHERE rs.raw_material_id = i.id
+                AND rs.shipment_id = s.id
+                AND i.order_id = $1
+                AND s.arrival_date IS NULL
+            "#,
+            order_id
+        )
+        .execute(con)
+        .await?
+        .rows_affected())
+    }
+
     pub async fn count_by_shipment_id(
         id: i64,
         con: &mut PgConnection,
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use sqlx::postgres::types::PgMoney;
 use sqlx::PgConnection;
 
@@ -14,6 +16,23 @@ pub struct Supplier {
 }
 
 impl Supplier {
+    #[cfg(test)]
+    pub fn new(
+        id: i64,
+        raw_material_kind: RawMaterial,
+        min_order_quantity: i32,
+        unit_price: i64,
+        delivery_time: i32,
+    ) -> Self {
+        Self {
+            id,
+            raw_material_kind,
+            min_order_quantity,
+            unit_price: PgMoney(unit_price),
+            delivery_time,
+        }
+    }
+
     pub fn can_deliver_in(&self, time: i32) -> bool {
         self.delivery_time <= time
     }
@@ -50,4 +69,24 @@ impl Supplier {
         .fetch_all(con)
         .await
     }
+
+    /// Shortest delivery time offered for each raw material.
+    pub async fn get_quickest_deliveries(
+        con: &mut PgConnection,
+    ) -> sqlx::Result<HashMap<RawMaterial, i32>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                raw_material_kind as "raw_material_kind: RawMaterial",
+                MIN(delivery_time) as "delivery_time!"
+            FROM suppliers
+            GROUP BY raw_material_kind
+            "#
+        )
+        .fetch_all(con)
+        .await?
+        .into_iter()
+        .map(|row| (row.raw_material_kind, row.delivery_time))
+        .collect())
+    }
 }
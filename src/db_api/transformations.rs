@@ -3,9 +3,20 @@ use sqlx::PgConnection;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::error::{Error, Result};
+
 use super::PieceKind;
 use super::ToolType;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[sqlx(type_name = "transformation_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TransformationStatus {
+    Pending,
+    Completed,
+    Canceled,
+}
+
 #[derive(Debug, Clone)]
 pub struct Transformation {
     id: Option<i64>,
@@ -13,6 +24,7 @@ pub struct Transformation {
     product_id: Uuid,
     recipe_id: i64,
     date: Option<i32>,
+    machine: Option<String>,
 }
 
 impl Transformation {
@@ -23,6 +35,7 @@ impl Transformation {
             product_id,
             recipe_id,
             date: None,
+            machine: None,
         }
     }
 
@@ -30,17 +43,22 @@ impl Transformation {
         self.date = Some(date);
     }
 
+    pub fn set_machine(&mut self, machine: impl ToString) {
+        self.machine = Some(machine.to_string());
+    }
+
     pub async fn insert(&mut self, con: &mut PgConnection) -> sqlx::Result<()> {
         self.id = Some(
             sqlx::query!(
                 r#"INSERT INTO transformations
-                    (material_id, product_id, recipe_id, date)
-                VALUES ($1, $2, $3, $4)
+                    (material_id, product_id, recipe_id, date, machine)
+                VALUES ($1, $2, $3, $4, $5)
                 RETURNING id"#,
                 self.material_id,
                 self.product_id,
                 self.recipe_id,
                 self.date,
+                self.machine,
             )
             .fetch_one(con)
             .await?
@@ -56,6 +74,7 @@ impl Transformation {
         line: &str,
         machine: &str,
         time_taken: i32,
+        changeover_time: i32,
         con: &mut PgConnection,
     ) -> sqlx::Result<()> {
         sqlx::query!(
@@ -65,12 +84,14 @@ impl Transformation {
                 date = $1,
                 line = $2,
                 machine = $3,
-                time_taken = $4
-            WHERE id = $5"#,
+                time_taken = $4,
+                changeover_time = $5
+            WHERE id = $6"#,
             completion_date as i32,
             line,
             machine,
             time_taken,
+            changeover_time,
             self.id
         )
         .execute(con)
@@ -78,6 +99,24 @@ impl Transformation {
         Ok(())
     }
 
+    pub async fn cancel_by_order(
+        order_id: Uuid,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<u64> {
+        Ok(sqlx::query!(
+            r#"UPDATE transformations AS t
+            SET status = 'canceled'
+            FROM items AS i
+            WHERE t.product_id = i.id
+                AND i.order_id = $1
+                AND t.status = 'pending'"#,
+            order_id
+        )
+        .execute(con)
+        .await?
+        .rows_affected())
+    }
+
     pub async fn get_n_next_raw_mat_transf(
         n: i64,
         con: &mut PgConnection,
@@ -102,10 +141,7 @@ impl Transformation {
         .collect())
     }
 
-    pub async fn get_by_id(
-        id: i64,
-        con: &mut PgConnection,
-    ) -> sqlx::Result<Self> {
+    pub async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Self> {
         sqlx::query_as!(
             Transformation,
             r#"
@@ -114,14 +150,16 @@ impl Transformation {
                 material_id,
                 product_id,
                 recipe_id,
-                date
+                date,
+                machine
             FROM transformations
             WHERE id = $1
             "#,
             id
         )
-        .fetch_one(con)
-        .await
+        .fetch_optional(con)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Transformation {}", id)))
     }
 
     async fn query_related_upstream(
@@ -133,8 +171,9 @@ impl Transformation {
 
         while let Some(related) = sqlx::query_as!(
             Transformation,
-            "SELECT id, material_id, product_id, recipe_id, date
-            FROM transformations WHERE material_id = $1",
+            "SELECT id, material_id, product_id, recipe_id, date, machine
+            FROM transformations
+            WHERE material_id = $1 AND status <> 'canceled'",
             query_id
         )
         .fetch_optional(pool)
@@ -156,7 +195,7 @@ impl Transformation {
 
         while let Some(related) = sqlx::query_as!(
             Transformation,
-            "SELECT id, material_id, product_id, recipe_id, date
+            "SELECT id, material_id, product_id, recipe_id, date, machine
             FROM transformations WHERE product_id = $1",
             query_id
         )
@@ -171,10 +210,7 @@ impl Transformation {
     }
 
     #[allow(dead_code)]
-    pub async fn get_related(
-        id: i64,
-        pool: &PgPool,
-    ) -> sqlx::Result<Vec<Self>> {
+    pub async fn get_related(id: i64, pool: &PgPool) -> Result<Vec<Self>> {
         let starting_transf = {
             let mut con = pool.acquire().await?;
             Self::get_by_id(id, &mut con).await?
@@ -201,6 +237,14 @@ impl Transformation {
     pub fn product_id(&self) -> Uuid {
         self.product_id
     }
+
+    pub fn date(&self) -> Option<i32> {
+        self.date
+    }
+
+    pub fn machine(&self) -> Option<&str> {
+        self.machine.as_deref()
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -212,6 +256,7 @@ pub struct TransformationDetails {
     pub product_kind: PieceKind,
     pub tool: ToolType,
     pub operation_time: i64,
+    pub machine: Option<String>,
 }
 
 impl TransformationDetails {
@@ -231,7 +276,9 @@ impl TransformationDetails {
             recipes.material_kind as "material_kind: PieceKind",
             recipes.product_kind as "product_kind: PieceKind",
             recipes.tool as "tool: ToolType",
-            recipes.operation_time
+            recipes.operation_time,
+
+            transformations.machine
 
             FROM transformations
 
@@ -261,13 +308,17 @@ impl TransformationDetails {
             recipes.material_kind as "material_kind: PieceKind",
             recipes.product_kind as "product_kind: PieceKind",
             recipes.tool as "tool: ToolType",
-            recipes.operation_time
+            recipes.operation_time,
+
+            t.machine
 
             FROM transformations AS t
 
             JOIN recipes ON t.recipe_id = recipes.id
 
-            WHERE t.material_id = $1
+            -- stock released by a canceled order is also the material of
+            -- its canceled transformations
+            WHERE t.material_id = $1 AND t.status <> 'canceled'
             "#,
             id
         )
@@ -275,3 +326,51 @@ impl TransformationDetails {
         .await
     }
 }
+
+/// A transformation of an order together with its recipe, whatever its
+/// status.
+#[derive(Debug, Serialize)]
+pub struct OrderTransformation {
+    pub transformation_id: i64,
+    pub material_id: Uuid,
+    pub product_id: Uuid,
+    pub material_kind: PieceKind,
+    pub product_kind: PieceKind,
+    pub tool: ToolType,
+    pub operation_time: i64,
+    pub status: TransformationStatus,
+    pub date: Option<i32>,
+    pub machine: Option<String>,
+}
+
+impl OrderTransformation {
+    pub async fn get_by_order(
+        order_id: Uuid,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            OrderTransformation,
+            r#"
+            SELECT
+                t.id as transformation_id,
+                t.material_id,
+                t.product_id,
+                r.material_kind as "material_kind: PieceKind",
+                r.product_kind as "product_kind: PieceKind",
+                r.tool as "tool: ToolType",
+                r.operation_time,
+                t.status as "status: TransformationStatus",
+                t.date,
+                t.machine
+            FROM transformations AS t
+            JOIN recipes AS r ON t.recipe_id = r.id
+            JOIN items AS i ON t.product_id = i.id
+            WHERE i.order_id = $1
+            ORDER BY t.date, t.id
+            "#,
+            order_id
+        )
+        .fetch_all(con)
+        .await
+    }
+}
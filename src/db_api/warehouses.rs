@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use sqlx::PgConnection;
+
+use crate::error::{Error, Result};
+
+use super::{ItemStatus, PieceKind};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warehouse {
+    code: String,
+    capacity: i32,
+    occupancy: i32,
+}
+
+impl Warehouse {
+    pub fn free_slots(&self) -> i32 {
+        (self.capacity - self.occupancy).max(0)
+    }
+
+    pub async fn get_all(con: &mut PgConnection) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            Warehouse,
+            r#"SELECT
+                code as "code!",
+                capacity as "capacity!",
+                occupancy as "occupancy!"
+            FROM warehouse_occupancy
+            ORDER BY code"#
+        )
+        .fetch_all(con)
+        .await
+    }
+
+    /// Checks that the warehouse `code` can store one more item.
+    ///
+    /// The warehouses stay locked until the end of the transaction, so that
+    /// concurrent entries cannot overfill them.
+    pub async fn check_free_slot(
+        code: &str,
+        con: &mut PgConnection,
+    ) -> Result<()> {
+        sqlx::query!("SELECT code FROM warehouses ORDER BY code FOR UPDATE")
+            .fetch_all(&mut *con)
+            .await?;
+
+        let warehouses = Self::get_all(con).await?;
+        check_free_slot(&warehouses, code)
+    }
+}
+
+fn check_free_slot(warehouses: &[Warehouse], code: &str) -> Result<()> {
+    let warehouse =
+        warehouses.iter().find(|w| w.code == code).ok_or_else(|| {
+            Error::Invalid(format!("Warehouse {} does not exist", code))
+        })?;
+
+    if warehouse.free_slots() > 0 {
+        return Ok(());
+    }
+
+    let alternative = warehouses
+        .iter()
+        .filter(|w| w.free_slots() > 0)
+        .max_by_key(|w| w.free_slots());
+
+    Err(Error::Conflict(match alternative {
+        Some(other) => format!(
+            "Warehouse {} is full, {} has {} free slots",
+            code,
+            other.code,
+            other.free_slots()
+        ),
+        None => format!("Warehouse {} is full, as is every other one", code),
+    }))
+}
+
+/// Stock of one kind of piece in a warehouse.
+#[derive(Debug, Serialize)]
+pub struct PieceStock {
+    pub piece_kind: PieceKind,
+    pub quantity: i64,
+}
+
+/// Occupancy and contents of a warehouse, as exposed by the API.
+#[derive(Debug, Serialize)]
+pub struct WarehouseDetails {
+    pub code: String,
+    pub capacity: i32,
+    pub occupancy: i32,
+    pub free_slots: i32,
+    pub contents: Vec<PieceStock>,
+}
+
+impl WarehouseDetails {
+    pub async fn get_all(con: &mut PgConnection) -> sqlx::Result<Vec<Self>> {
+        let mut details = Warehouse::get_all(&mut *con)
+            .await?
+            .into_iter()
+            .map(|w| WarehouseDetails {
+                free_slots: w.free_slots(),
+                code: w.code,
+                capacity: w.capacity,
+                occupancy: w.occupancy,
+                contents: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        let stock = sqlx::query!(
+            r#"SELECT
+                location as "location!",
+                piece_kind as "piece_kind: PieceKind",
+                COUNT(*) as "quantity!"
+            FROM items
+            WHERE status = $1 AND location IS NOT NULL
+            GROUP BY location, piece_kind
+            ORDER BY location, piece_kind"#,
+            ItemStatus::InStock as ItemStatus
+        )
+        .fetch_all(con)
+        .await?;
+
+        for row in stock {
+            if let Some(warehouse) =
+                details.iter_mut().find(|w| w.code == row.location)
+            {
+                warehouse.contents.push(PieceStock {
+                    piece_kind: row.piece_kind,
+                    quantity: row.quantity,
+                });
+            }
+        }
+
+        Ok(details)
+    }
+}
+
+/// Projected number of items stored in the warehouses, day by day, from the
+/// shipments that have yet to arrive and the raw materials that pending
+/// transformations will take out of stock.
+#[derive(Debug, Clone)]
+pub struct StockProjection {
+    capacity: i64,
+    current_date: i32,
+    occupancy: i64,
+    /// items arriving and leaving on each day
+    changes: BTreeMap<i32, (i64, i64)>,
+}
+
+impl StockProjection {
+    pub fn new(capacity: i64, occupancy: i64, current_date: i32) -> Self {
+        Self {
+            capacity,
+            current_date,
+            occupancy,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    pub async fn load(
+        current_date: i32,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Self> {
+        let warehouses = Warehouse::get_all(&mut *con).await?;
+        let mut projection = Self::new(
+            warehouses.iter().map(|w| w.capacity as i64).sum(),
+            warehouses.iter().map(|w| w.occupancy as i64).sum(),
+            current_date,
+        );
+
+        let arrivals = sqlx::query!(
+            r#"
+            SELECT
+                ship.request_date + sup.delivery_time as "day!",
+                SUM(ship.quantity) as "quantity!"
+            FROM shipments AS ship
+            JOIN suppliers AS sup ON ship.supplier_id = sup.id
+            WHERE ship.arrival_date IS NULL
+            GROUP BY 1
+            "#
+        )
+        .fetch_all(&mut *con)
+        .await?;
+        for row in arrivals {
+            projection.add_arrivals(row.day, row.quantity);
+        }
+
+        // only raw materials that are in stock or bought are counted, the
+        // others never enter the warehouses
+        let consumption = sqlx::query!(
+            r#"
+            SELECT t.date as "day!", COUNT(*) as "quantity!"
+            FROM transformations AS t
+            JOIN items AS i ON i.id = t.material_id
+            JOIN pieces AS p ON p.code = i.piece_kind
+            WHERE t.status = 'pending'
+                AND t.date IS NOT NULL
+                AND p.category = 'raw'
+                AND (i.status = $1 OR EXISTS (
+                    SELECT 1 FROM raw_material_shipments AS rs
+                    WHERE rs.raw_material_id = i.id
+                ))
+            GROUP BY t.date
+            "#,
+            ItemStatus::InStock as ItemStatus
+        )
+        .fetch_all(con)
+        .await?;
+        for row in consumption {
+            projection.add_consumption(row.day, row.quantity);
+        }
+
+        Ok(projection)
+    }
+
+    /// Late arrivals and transformations are expected on the current date.
+    pub fn add_arrivals(&mut self, day: i32, quantity: i64) {
+        let day = day.max(self.current_date);
+        self.changes.entry(day).or_default().0 += quantity;
+    }
+
+    pub fn add_consumption(&mut self, day: i32, quantity: i64) {
+        let day = day.max(self.current_date);
+        self.changes.entry(day).or_default().1 += quantity;
+    }
+
+    /// Largest number of items over capacity from `day` onwards if
+    /// `quantity` more items arrive on `day` and `consumed` of them leave on
+    /// that same day, 0 when they fit.
+    pub fn overflow(&self, day: i32, quantity: i64, consumed: i64) -> i64 {
+        let mut projection = self.clone();
+        projection.add_arrivals(day, quantity);
+        projection.add_consumption(day, consumed);
+        let day = day.max(self.current_date);
+
+        // items arrive before the ones of the same day leave
+        let mut occupancy = projection.occupancy;
+        let mut overflow = 0;
+        for (d, (arrived, left)) in projection.changes {
+            occupancy += arrived;
+            if d >= day {
+                overflow = overflow.max(occupancy - projection.capacity);
+            }
+            occupancy -= left;
+        }
+        overflow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, ResponseError};
+
+    use super::*;
+
+    fn warehouse(code: &str, occupancy: i32) -> Warehouse {
+        Warehouse {
+            code: code.to_string(),
+            capacity: 32,
+            occupancy,
+        }
+    }
+
+    #[test]
+    fn full_warehouses_are_refused() {
+        let warehouses = [warehouse("W1", 32), warehouse("W2", 30)];
+
+        assert!(check_free_slot(&warehouses, "W2").is_ok());
+
+        let e = check_free_slot(&warehouses, "W1").unwrap_err();
+        assert_eq!(e.to_string(), "Warehouse W1 is full, W2 has 2 free slots");
+        assert_eq!(e.status_code(), StatusCode::CONFLICT);
+
+        let e = check_free_slot(&warehouses, "W3").unwrap_err();
+        assert_eq!(e.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let warehouses = [warehouse("W1", 32), warehouse("W2", 32)];
+        let e = check_free_slot(&warehouses, "W2").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Warehouse W2 is full, as is every other one"
+        );
+    }
+
+    #[test]
+    fn projected_overflow() {
+        let mut projection = StockProjection::new(64, 50, 2);
+        projection.add_arrivals(4, 8);
+        projection.add_consumption(5, 20);
+        projection.add_arrivals(7, 16);
+
+        // 58 items on day 4, 38 after day 5 and 54 from day 7
+        assert_eq!(projection.overflow(3, 6, 0), 0);
+        assert_eq!(projection.overflow(3, 16, 6), 4);
+        assert_eq!(projection.overflow(5, 16, 0), 10);
+        // the items used on the day they arrive only count on that day
+        assert_eq!(projection.overflow(6, 16, 10), 0);
+        assert_eq!(projection.overflow(6, 20, 4), 6);
+        // overdue shipments are expected today
+        assert_eq!(projection.overflow(0, 7, 0), 1);
+    }
+}
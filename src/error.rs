@@ -0,0 +1,148 @@
+use std::fmt::Display;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+
+/// Errors of the db_api and of the routes, each kind answered with its own
+/// HTTP status and a JSON body.
+#[derive(Debug)]
+pub enum Error {
+    /// the requested resource does not exist
+    NotFound(String),
+    /// the request does not fit the current state, e.g. an item that is not
+    /// in the status a transition expects or a duplicate
+    Conflict(String),
+    /// the request was read but its values are not acceptable
+    Invalid(String),
+    /// the request could not be read
+    BadRequest(String),
+    Internal(anyhow::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    message: String,
+}
+
+impl Error {
+    pub fn not_found(what: impl Display) -> Self {
+        Error::NotFound(format!("{} not found", what))
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::Conflict(_) => "conflict",
+            Error::Invalid(_) => "invalid",
+            Error::BadRequest(_) => "bad_request",
+            Error::Internal(_) => "internal",
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound(message)
+            | Error::Conflict(message)
+            | Error::Invalid(message)
+            | Error::BadRequest(message) => write!(f, "{}", message),
+            Error::Internal(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Internal(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Error::NotFound(e.to_string()),
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                Error::Conflict(e.message().to_string())
+            }
+            sqlx::Error::Database(e)
+                if e.is_check_violation() || e.is_foreign_key_violation() =>
+            {
+                Error::Invalid(e.message().to_string())
+            }
+            e => Error::Internal(e.into()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<Error>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        match e.downcast::<sqlx::Error>() {
+            Ok(e) => e.into(),
+            Err(e) => Error::Internal(e),
+        }
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            // details of internal errors are only logged
+            Error::Internal(_) => "internal server error".to_string(),
+            e => e.to_string(),
+        };
+
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.code(),
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn internal_details_are_hidden() {
+        let e: Error = anyhow::anyhow!("connection refused").into();
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = actix_web::body::to_bytes(e.error_response().into_body())
+            .await
+            .unwrap();
+        assert_eq!(
+            body,
+            r#"{"error":"internal","message":"internal server error"}"#
+        );
+    }
+
+    #[test]
+    fn wrapped_errors_keep_their_kind() {
+        let e: Error = anyhow::Error::from(Error::not_found("Item 1")).into();
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+
+        let e: Error = anyhow::Error::from(sqlx::Error::RowNotFound).into();
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,196 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::db_api::Ingestion;
+
+use super::parser::ParseError;
+
+/// What happened to a single order of a command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "detail", rename_all = "snake_case")]
+pub enum Outcome {
+    Inserted(Uuid),
+    /// the same order was already received, the id is the existing order
+    Unchanged(Uuid),
+    /// the client already placed a different order with the same number,
+    /// the id is the existing order
+    Conflict(Uuid),
+    /// the order was read but its values were refused by the database
+    Invalid(String),
+    /// the order broke validation rules whose policy is to reject it
+    Rejected(Vec<String>),
+    ParseError(ParseError),
+    Failed,
+}
+
+impl From<Ingestion> for Outcome {
+    fn from(ingestion: Ingestion) -> Self {
+        match ingestion {
+            Ingestion::Inserted(id) => Outcome::Inserted(id),
+            Ingestion::Unchanged(id) => Outcome::Unchanged(id),
+            Ingestion::Conflict(id) => Outcome::Conflict(id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrderAck {
+    pub number: Option<String>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    /// validation rules the order was accepted despite breaking
+    pub warnings: Vec<String>,
+}
+
+/// Reply sent back to the sender of a command.
+#[derive(Debug, Default, Serialize)]
+pub struct Acknowledgement {
+    pub client: Option<String>,
+    pub orders: Vec<OrderAck>,
+    /// set when the command as a whole could not be read
+    pub error: Option<ParseError>,
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn position(e: &ParseError) -> String {
+    format!(
+        r#" Line="{}" Column="{}" Reason="{}""#,
+        e.line,
+        e.column,
+        escape(&e.message)
+    )
+}
+
+impl Acknowledgement {
+    pub fn rejected(error: ParseError) -> Self {
+        Self {
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml =
+            String::from("<?xml version=\"1.0\"?>\n<ACKNOWLEDGEMENT>\n");
+
+        if let Some(client) = &self.client {
+            xml += &format!("<Client NameId=\"{}\"/>\n", escape(client));
+        }
+
+        if let Some(e) = &self.error {
+            xml += &format!("<Error{}/>\n", position(e));
+        }
+
+        for order in &self.orders {
+            xml += "<Order";
+            if let Some(number) = &order.number {
+                xml += &format!(r#" Number="{}""#, escape(number));
+            }
+            xml += &match &order.outcome {
+                Outcome::Inserted(id) => {
+                    format!(r#" Status="inserted" Id="{}""#, id)
+                }
+                Outcome::Unchanged(id) => {
+                    format!(r#" Status="unchanged" Id="{}""#, id)
+                }
+                Outcome::Conflict(id) => {
+                    format!(r#" Status="conflict" Id="{}""#, id)
+                }
+                Outcome::Invalid(reason) => {
+                    format!(r#" Status="invalid" Reason="{}""#, escape(reason))
+                }
+                Outcome::Rejected(reasons) => format!(
+                    r#" Status="rejected" Reason="{}""#,
+                    escape(&reasons.join("; "))
+                ),
+                Outcome::ParseError(e) => {
+                    format!(r#" Status="parse_error"{}"#, position(e))
+                }
+                Outcome::Failed => r#" Status="failed""#.to_string(),
+            };
+            if !order.warnings.is_empty() {
+                xml += &format!(
+                    r#" Warnings="{}""#,
+                    escape(&order.warnings.join("; "))
+                );
+            }
+            xml += "/>\n";
+        }
+
+        xml += "</ACKNOWLEDGEMENT>\n";
+        xml
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_every_order_outcome() {
+        let id = Uuid::nil();
+        let ack = Acknowledgement {
+            client: Some("Smith & Sons".to_string()),
+            orders: vec![
+                OrderAck {
+                    number: Some("18".to_string()),
+                    outcome: Outcome::Inserted(id),
+                    warnings: vec!["lead_time: too soon".to_string()],
+                },
+                OrderAck {
+                    number: Some("19".to_string()),
+                    outcome: Outcome::Conflict(id),
+                    warnings: Vec::new(),
+                },
+                OrderAck {
+                    number: Some("20".to_string()),
+                    outcome: Outcome::Rejected(vec![
+                        "quantity: too many".to_string(),
+                        "due_date: too late".to_string(),
+                    ]),
+                    warnings: Vec::new(),
+                },
+                OrderAck {
+                    number: None,
+                    outcome: Outcome::ParseError(ParseError {
+                        line: 4,
+                        column: 1,
+                        order_number: None,
+                        message: "missing attribute \"Number\"".to_string(),
+                    }),
+                    warnings: Vec::new(),
+                },
+            ],
+            error: None,
+        };
+
+        assert_eq!(
+            ack.to_xml(),
+            format!(
+                r#"<?xml version="1.0"?>
+<ACKNOWLEDGEMENT>
+<Client NameId="Smith &amp; Sons"/>
+<Order Number="18" Status="inserted" Id="{id}" Warnings="lead_time: too soon"/>
+<Order Number="19" Status="conflict" Id="{id}"/>
+<Order Number="20" Status="rejected" Reason="quantity: too many; due_date: too late"/>
+<Order Status="parse_error" Line="4" Column="1" Reason="missing attribute &quot;Number&quot;"/>
+</ACKNOWLEDGEMENT>
+"#
+            )
+        );
+    }
+}
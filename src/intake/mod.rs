@@ -0,0 +1,266 @@
+mod acknowledgement;
+mod parser;
+
+use std::sync::{Arc, RwLock};
+
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{PricingSettings, ValidationSettings},
+    db_api::ClientOrder,
+    scheduler::RecipeGraph,
+    validation::ValidationContext,
+};
+
+pub use acknowledgement::{Acknowledgement, OrderAck, Outcome};
+pub use parser::ParseError;
+
+/// Validates and stores the orders received by every intake channel, so that
+/// UDP, TCP and HTTP clients get the same treatment.
+#[derive(Clone)]
+pub struct Intake {
+    pool: PgPool,
+    pricing: Arc<PricingSettings>,
+    validation: Arc<ValidationSettings>,
+    recipes: Arc<RwLock<RecipeGraph>>,
+}
+
+impl Intake {
+    pub fn new(
+        pool: PgPool,
+        pricing: PricingSettings,
+        validation: ValidationSettings,
+        recipes: Arc<RwLock<RecipeGraph>>,
+    ) -> Self {
+        Self {
+            pool,
+            pricing: Arc::new(pricing),
+            validation: Arc::new(validation),
+            recipes,
+        }
+    }
+
+    /// Receives a raw command, which must be UTF-8 encoded XML.
+    pub async fn receive_bytes(&self, message: &[u8]) -> Acknowledgement {
+        match std::str::from_utf8(message) {
+            Ok(message) => self.receive_xml(message).await,
+            Err(e) => {
+                tracing::error!("Invalid command, {}", e);
+                Acknowledgement::rejected(ParseError {
+                    line: 1,
+                    column: 1,
+                    order_number: None,
+                    message: e.to_string(),
+                })
+            }
+        }
+    }
+
+    pub async fn receive_xml(&self, message: &str) -> Acknowledgement {
+        tracing::trace!("Received message: {}", message);
+
+        match parser::parse_command(message) {
+            Ok(command) => {
+                self.receive(
+                    Some(command.client),
+                    command.orders,
+                    command.errors,
+                )
+                .await
+            }
+            Err(e) => {
+                tracing::error!("Invalid command, {}", e);
+                Acknowledgement::rejected(e)
+            }
+        }
+    }
+
+    /// Validates and stores `orders`, acknowledging each of them along with
+    /// the orders that could not be read.
+    pub async fn receive(
+        &self,
+        client: Option<String>,
+        orders: Vec<ClientOrder>,
+        errors: Vec<ParseError>,
+    ) -> Acknowledgement {
+        let mut ack = Acknowledgement {
+            client,
+            ..Default::default()
+        };
+
+        for e in errors {
+            tracing::error!("Rejected order, {}", e);
+            ack.orders.push(OrderAck {
+                number: e.order_number.clone(),
+                outcome: Outcome::ParseError(e),
+                warnings: Vec::new(),
+            });
+        }
+
+        let context = match self.pool.acquire().await {
+            Ok(mut con) => {
+                ValidationContext::load(&self.recipes, &mut con).await
+            }
+            Err(e) => Err(e.into()),
+        };
+        let context = match context {
+            Ok(context) => context,
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                for order in orders {
+                    ack.orders.push(OrderAck {
+                        number: Some(order.order_number.to_string()),
+                        outcome: Outcome::Failed,
+                        warnings: Vec::new(),
+                    });
+                }
+                return ack;
+            }
+        };
+
+        for order in orders {
+            ack.orders.push(self.receive_order(order, &context).await);
+        }
+
+        ack
+    }
+
+    async fn receive_order(
+        &self,
+        mut order: ClientOrder,
+        context: &ValidationContext,
+    ) -> OrderAck {
+        let number = Some(order.order_number.to_string());
+
+        if order.unit_price.is_none() {
+            order.unit_price = self.pricing.unit_price(order.work_piece);
+        }
+
+        // resends are acknowledged like the first time, even once the order
+        // would no longer pass validation
+        match order.find_resent(&self.pool).await {
+            Ok(Some(ingestion)) => {
+                return OrderAck {
+                    number,
+                    outcome: ingestion.into(),
+                    warnings: Vec::new(),
+                }
+            }
+            Ok(None) => (),
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                return OrderAck {
+                    number,
+                    outcome: Outcome::Failed,
+                    warnings: Vec::new(),
+                };
+            }
+        }
+
+        let validation = context.validate(&order, &self.validation);
+        if validation.is_rejected() {
+            tracing::warn!(
+                "Rejected order {} of '{}': {}",
+                order.order_number,
+                order.client_name,
+                validation.rejections.join("; ")
+            );
+            return OrderAck {
+                number,
+                outcome: Outcome::Rejected(validation.rejections),
+                warnings: validation.warnings,
+            };
+        }
+        for warning in &validation.warnings {
+            tracing::warn!(
+                "Order {} of '{}': {}",
+                order.order_number,
+                order.client_name,
+                warning
+            );
+        }
+        order.flags = validation.flags;
+
+        let outcome = match order.insert_to_db(&self.pool).await {
+            Ok(ingestion) => ingestion.into(),
+            Err(sqlx::Error::Database(e)) if e.is_check_violation() => {
+                tracing::error!("{:?}", e);
+                Outcome::Invalid(e.message().to_string())
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                Outcome::Failed
+            }
+        };
+
+        OrderAck {
+            number,
+            outcome,
+            warnings: validation.warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        configuration::get_configuration,
+        db_api::{self, FinalPiece},
+    };
+
+    fn order(quantity: i32) -> ClientOrder {
+        ClientOrder::new(
+            "Client".to_string(),
+            1,
+            FinalPiece::P5,
+            quantity,
+            30,
+            0,
+            0,
+        )
+    }
+
+    #[tokio::test]
+    async fn resent_orders_are_not_validated_again() {
+        let pool = get_configuration()
+            .expect("Failed to read configuration")
+            .database
+            .create_test_db()
+            .await;
+        let recipes = {
+            let mut con = pool.acquire().await.expect("Failed to acquire");
+            RecipeGraph::load(&mut con).await.expect("Invalid recipes")
+        };
+        let intake = Intake::new(
+            pool.clone(),
+            PricingSettings::default(),
+            ValidationSettings::default(),
+            Arc::new(RwLock::new(recipes)),
+        );
+
+        let ack = intake.receive(None, vec![order(4)], Vec::new()).await;
+        let Outcome::Inserted(id) = ack.orders[0].outcome else {
+            panic!("Order not inserted: {:?}", ack.orders[0]);
+        };
+
+        // the due date has passed
+        let mut con = pool.acquire().await.expect("Failed to acquire");
+        db_api::update_date(40, &mut con)
+            .await
+            .expect("Failed to set day");
+
+        let ack = intake.receive(None, vec![order(4)], Vec::new()).await;
+        assert_eq!(ack.orders[0].outcome, Outcome::Unchanged(id));
+
+        let ack = intake.receive(None, vec![order(5)], Vec::new()).await;
+        assert_eq!(ack.orders[0].outcome, Outcome::Conflict(id));
+
+        let new_order = ClientOrder {
+            order_number: 2,
+            ..order(4)
+        };
+        let ack = intake.receive(None, vec![new_order], Vec::new()).await;
+        assert!(matches!(ack.orders[0].outcome, Outcome::Rejected(_)));
+    }
+}
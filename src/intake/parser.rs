@@ -0,0 +1,339 @@
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::db_api::{ClientOrder, FinalPiece};
+
+/// Problem found while parsing a command, located in the original message.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ParseError {
+    pub line: u32,
+    pub column: u32,
+    /// number of the order the error refers to, as written in the message
+    pub order_number: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        if let Some(number) = &self.order_number {
+            write!(f, "order {}: ", number)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Orders read from a command, along with every order that was rejected.
+#[derive(Debug, Default)]
+pub struct ParsedCommand {
+    pub client: String,
+    pub orders: Vec<ClientOrder>,
+    pub errors: Vec<ParseError>,
+}
+
+fn parse_euros_and_cents(input: &str) -> anyhow::Result<i64> {
+    let (euros_str, cents_str) = match input.split_once(',') {
+        Some((euros, cents)) => (euros, cents),
+        None => match input.split_once('.') {
+            Some((euros, cents)) => (euros, cents),
+            None => anyhow::bail!("Invalid currency separator"),
+        },
+    };
+
+    let cents = match cents_str.len() {
+        1 => cents_str.parse::<i64>()? * 10,
+        2 => cents_str.parse::<i64>()?,
+        _ => anyhow::bail!("Invalid number of cents"),
+    };
+
+    let euros = match euros_str.parse::<i64>() {
+        Ok(e) => e * 100,
+        Err(e) => anyhow::bail!("{}", e),
+    };
+
+    Ok(euros + cents)
+}
+
+fn parse_money(input: &str) -> anyhow::Result<i64> {
+    let input = input.replace('€', "");
+    let has_cents = input.contains('.') || input.contains(',');
+
+    if has_cents {
+        return match parse_euros_and_cents(&input) {
+            Ok(money) => Ok(money),
+            Err(e) => Err(anyhow::anyhow!("{}", e)),
+        };
+    }
+
+    match input.parse::<i64>() {
+        Ok(money) => Ok(money * 100),
+        Err(e) => Err(anyhow::anyhow!("{}", e)),
+    }
+}
+
+fn error_at(
+    doc: &Document,
+    node: Node,
+    order_number: Option<&str>,
+    message: impl ToString,
+) -> ParseError {
+    let pos = doc.text_pos_at(node.range().start);
+    ParseError {
+        line: pos.row,
+        column: pos.col,
+        order_number: order_number.map(str::to_string),
+        message: message.to_string(),
+    }
+}
+
+fn required<'a>(node: Node<'a, '_>, name: &str) -> Result<&'a str, String> {
+    node.attribute(name)
+        .map(str::trim)
+        .ok_or_else(|| format!("missing attribute {}", name))
+}
+
+fn parse_int(node: Node, name: &str) -> Result<i32, String> {
+    required(node, name)?
+        .parse()
+        .map_err(|e| format!("invalid {}: {}", name, e))
+}
+
+fn parse_money_attr(node: Node, name: &str) -> Result<Option<i64>, String> {
+    node.attribute(name)
+        .map(|value| parse_money(value.trim()))
+        .transpose()
+        .map_err(|e| format!("invalid {}: {}", name, e))
+}
+
+fn parse_order(node: Node, client_name: &str) -> Result<ClientOrder, String> {
+    let piece = required(node, "WorkPiece")?;
+    let piece =
+        FinalPiece::try_from(piece).map_err(|e| format!("{}: {}", e, piece))?;
+    let late_penalty = parse_money_attr(node, "LatePen")?
+        .ok_or("missing attribute LatePen")?;
+    let early_penalty = parse_money_attr(node, "EarlyPen")?
+        .ok_or("missing attribute EarlyPen")?;
+
+    Ok(ClientOrder {
+        client_name: client_name.to_string(),
+        order_number: parse_int(node, "Number")?,
+        work_piece: piece,
+        quantity: parse_int(node, "Quantity")?,
+        due_date: parse_int(node, "DueDate")?,
+        late_penalty,
+        early_penalty,
+        unit_price: parse_money_attr(node, "UnitPrice")?,
+        flags: Vec::new(),
+    })
+}
+
+/// Parses a client command.
+///
+/// Fails only if the message is not well formed XML or does not name a
+/// client, invalid orders are reported in [`ParsedCommand::errors`] and the
+/// remaining orders are still returned.
+pub fn parse_command(input: &str) -> Result<ParsedCommand, ParseError> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let doc = Document::parse_with_options(input, options).map_err(|e| {
+        let pos = e.pos();
+        ParseError {
+            line: pos.row,
+            column: pos.col,
+            order_number: None,
+            message: e.to_string(),
+        }
+    })?;
+
+    let root = doc.root_element();
+    if !root.has_tag_name("DOCUMENT") {
+        return Err(error_at(
+            &doc,
+            root,
+            None,
+            format!("unexpected root element {}", root.tag_name().name()),
+        ));
+    }
+
+    let mut clients = root.children().filter(|n| n.has_tag_name("Client"));
+    let client_name = match clients.next() {
+        Some(client) => match client.attribute("NameId").map(str::trim) {
+            Some(name) if !name.is_empty() => name,
+            _ => {
+                return Err(error_at(&doc, client, None, "missing client name"))
+            }
+        },
+        None => return Err(error_at(&doc, root, None, "missing client")),
+    };
+
+    let mut command = ParsedCommand {
+        client: client_name.to_string(),
+        ..Default::default()
+    };
+    for client in clients {
+        command.errors.push(error_at(
+            &doc,
+            client,
+            None,
+            "only one client per command is allowed",
+        ));
+    }
+
+    for node in root.children().filter(Node::is_element) {
+        match node.tag_name().name() {
+            "Client" => continue,
+            "Order" => match parse_order(node, client_name) {
+                Ok(order) => command.orders.push(order),
+                Err(e) => command.errors.push(error_at(
+                    &doc,
+                    node,
+                    node.attribute("Number"),
+                    e,
+                )),
+            },
+            other => command.errors.push(error_at(
+                &doc,
+                node,
+                None,
+                format!("unexpected element {}", other),
+            )),
+        }
+    }
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    use crate::db_api::ClientOrder;
+
+    #[rstest]
+    #[case("tests/mock_commands/command1.xml",
+        vec![
+            ClientOrder::new("Client AA".to_string(), 18, FinalPiece::P5, 8, 7, 1000, 500),
+            ClientOrder::new("Client AA".to_string(), 19, FinalPiece::P6, 1, 4, 1000, 1000)
+        ]
+    )]
+    #[case("tests/mock_commands/command2a.xml",
+        vec![
+            ClientOrder::new("Client AA".to_string(), 41, FinalPiece::P5, 3, 10, 1000, 500),
+            ClientOrder::new("Client AA".to_string(), 42, FinalPiece::P9, 2, 8, 1000, 500)
+        ]
+    )]
+    #[case("tests/mock_commands/command2b.xml",
+        vec![
+            ClientOrder::new("Client BB".to_string(), 47, FinalPiece::P6, 8, 6, 1000, 500),
+            ClientOrder::new("Client BB".to_string(), 46, FinalPiece::P7, 2, 6, 1000, 500)
+        ]
+    )]
+    #[case("tests/mock_commands/command3.xml",
+        vec![
+            ClientOrder::new("Client CC".to_string(), 905, FinalPiece::P5, 3, 8, 1000, 500),
+            ClientOrder::new("Client CC".to_string(), 906, FinalPiece::P6, 2, 8, 1000, 200)
+        ]
+    )]
+    #[case("tests/mock_commands/command4.xml",
+        vec![
+            ClientOrder::new("Client BB".to_string(), 42, FinalPiece::P7, 3, 12, 1000, 500),
+            ClientOrder::new("Client BB".to_string(), 43, FinalPiece::P7, 2, 12, 1500, 500),
+            ClientOrder::new("Client BB".to_string(), 44, FinalPiece::P6, 4, 12, 1500, 200),
+            ClientOrder::new("Client BB".to_string(), 45, FinalPiece::P5, 8, 11, 1500, 200),
+        ]
+    )]
+    #[case("tests/mock_commands/command5.xml",
+        vec![
+            ClientOrder::new("Client CC".to_string(), 991, FinalPiece::P9, 5, 10, 2000, 500),
+        ]
+    )]
+    #[case("tests/mock_commands/command6.xml",
+        vec![
+            ClientOrder::new("Client DD".to_string(), 7, FinalPiece::P5, 4, 9, 1000, 500)
+                .with_unit_price(9550),
+            ClientOrder::new("Client DD".to_string(), 8, FinalPiece::P7, 2, 9, 1000, 500),
+        ]
+    )]
+    fn parse_mock_command(
+        #[case] filepath: String,
+        #[case] expected: Vec<ClientOrder>,
+    ) {
+        let input = std::fs::read_to_string(filepath).expect("File not found");
+
+        let command = parse_command(&input).unwrap();
+
+        assert!(command.errors.is_empty());
+        assert_eq!(command.orders, expected);
+    }
+
+    #[test]
+    fn accepts_any_attribute_order_and_quoting() {
+        let input = r#"<?xml version="1.0"?>
+<DOCUMENT>
+  <!-- orders for this week -->
+  <Client NameId='Smith &amp; Sons' />
+  <Order EarlyPen="5" LatePen='10' DueDate="7"
+         Quantity="2" WorkPiece="P6" Number="3"></Order>
+</DOCUMENT>"#;
+
+        let command = parse_command(input).unwrap();
+
+        assert!(command.errors.is_empty());
+        assert_eq!(
+            command.orders,
+            vec![ClientOrder::new(
+                "Smith & Sons".to_string(),
+                3,
+                FinalPiece::P6,
+                2,
+                7,
+                1000,
+                500
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_orders_with_their_position() {
+        let input = r#"<DOCUMENT>
+<Client NameId="Client AA"/>
+<Order Number="1" WorkPiece="P5" Quantity="1" DueDate="4" LatePen="1" EarlyPen="1"/>
+<Order Number="2" WorkPiece="P1" Quantity="1" DueDate="4" LatePen="1" EarlyPen="1"/>
+  <Order Number="3" WorkPiece="P5" DueDate="4" LatePen="1" EarlyPen="1"/>
+</DOCUMENT>"#;
+
+        let command = parse_command(input).unwrap();
+
+        assert_eq!(command.orders.len(), 1);
+        assert_eq!(
+            command.errors,
+            vec![
+                ParseError {
+                    line: 4,
+                    column: 1,
+                    order_number: Some("2".to_string()),
+                    message: "Invalid work piece: P1".to_string(),
+                },
+                ParseError {
+                    line: 5,
+                    column: 3,
+                    order_number: Some("3".to_string()),
+                    message: "missing attribute Quantity".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_malformed_documents() {
+        let input = "<DOCUMENT>\n<Client NameId=\"A\">\n</DOCUMENT>";
+        let err = parse_command(input).unwrap_err();
+
+        assert_eq!(err.line, 3, "{err}");
+        assert!(parse_command("<DOCUMENT></DOCUMENT>").is_err());
+    }
+}
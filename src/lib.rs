@@ -2,10 +2,14 @@
 
 mod configuration;
 mod db_api;
+mod error;
+mod intake;
 mod routes;
 mod scheduler;
 mod startup;
+mod tcp_listener;
 mod udp_listener;
+mod validation;
 
 pub use configuration::*;
 pub use startup::*;
@@ -4,18 +4,35 @@ use infi_erp::AppBuilder;
 async fn main() -> Result<(), anyhow::Error> {
     let settings = infi_erp::get_configuration()?;
 
-    let app = AppBuilder::new(settings.database.connection_string())
+    let mut app = AppBuilder::new(settings.database.connection_string())
         .with_udp_listener(
+            &settings.application.udp_hosts,
             settings.application.udp_port,
             settings.application.udp_buffer_size,
         )
+        .with_udp_allowed_sources(settings.application.udp_allowed_sources)
         .with_web_server(
             settings.application.http_host.as_str(),
             settings.application.http_port,
         )
-        .with_tracing_level(tracing::Level::INFO)
-        .build()
-        .await?;
+        .with_pricing(settings.pricing)
+        .with_validation(settings.validation)
+        .with_scheduler(settings.scheduler)
+        .with_tracing_level(tracing::Level::INFO);
+
+    if let Some(port) = settings.application.tcp_port {
+        app = app.with_tcp_listener(
+            &settings.application.tcp_hosts,
+            port,
+            settings.application.tcp_max_message_size,
+        );
+    }
+
+    if settings.application.http_order_intake {
+        app = app.with_http_order_intake();
+    }
+
+    let app = app.build().await?;
 
     if let Err(e) = app.run().await {
         tracing::error!("{:?}", e)
@@ -0,0 +1,48 @@
+use std::{future::Future, ops::Deref, pin::Pin};
+
+use actix_web::{
+    dev::Payload,
+    http::header::{ContentType, Header},
+    web::{Form, Json},
+    FromRequest, HttpRequest,
+};
+use serde::de::DeserializeOwned;
+
+/// Request body read as JSON when sent with a JSON content type and as an
+/// url encoded form otherwise, so both decode into the same struct and go
+/// through the same checks.
+#[derive(Debug)]
+pub struct Body<T>(pub T);
+
+impl<T> Body<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Body<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+fn is_json(req: &HttpRequest) -> bool {
+    ContentType::parse(req).is_ok_and(|ct| ct.subtype() == "json")
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Body<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if is_json(req) {
+            let json = Json::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(Body(json.await?.into_inner())) })
+        } else {
+            let form = Form::<T>::from_request(req, payload);
+            Box::pin(async move { Ok(Body(form.await?.into_inner())) })
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use actix_web::{
+    get,
+    web::{Data, Path, Query},
+    HttpResponse, Responder,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db_api::{Item, ItemDetails, ItemFilter, ItemMovement, Location};
+
+use super::{error_response, PageQuery};
+
+#[derive(Debug, Serialize)]
+struct InventoryPage {
+    page: u32,
+    per_page: u32,
+    total: i64,
+    items: Vec<ItemDetails>,
+}
+
+#[get("/items/{id}")]
+pub async fn get_item(path: Path<Uuid>, pool: Data<PgPool>) -> impl Responder {
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    match ItemDetails::get_by_id(path.into_inner(), &mut con).await {
+        Ok(item) => HttpResponse::Ok().json(item),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/items/{id}/history")]
+pub async fn get_item_history(
+    path: Path<Uuid>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    let item_id = path.into_inner();
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = Item::get_by_id(item_id, &mut con).await {
+        return error_response(e);
+    }
+
+    match ItemMovement::get_by_item(item_id, &mut con).await {
+        Ok(movements) => HttpResponse::Ok().json(movements),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/inventory")]
+pub async fn get_inventory(
+    filter: Query<ItemFilter>,
+    page: Query<PageQuery>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    if let Some(response) = page.check() {
+        return response;
+    }
+
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    // a typo in a location would otherwise look like an empty one
+    if let Some(code) = &filter.location {
+        if let Err(e) = Location::get(code, &mut con).await {
+            return error_response(e);
+        }
+    }
+
+    let total = match ItemDetails::count_filtered(&filter, &mut con).await {
+        Ok(total) => total,
+        Err(e) => return error_response(e),
+    };
+
+    let items = match ItemDetails::get_filtered(
+        &filter,
+        page.limit(),
+        page.offset(),
+        &mut con,
+    )
+    .await
+    {
+        Ok(items) => items,
+        Err(e) => return error_response(e),
+    };
+
+    HttpResponse::Ok().json(InventoryPage {
+        page: page.page,
+        per_page: page.per_page,
+        total,
+        items,
+    })
+}
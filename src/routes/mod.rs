@@ -1,30 +1,107 @@
-use std::{
-    collections::HashSet,
-    fmt::{Debug, Display},
-};
+mod body;
+mod items;
+mod orders;
+mod recipes;
+
+pub use items::*;
+pub use orders::*;
+pub use recipes::*;
+
+use std::{collections::HashSet, fmt::Display};
 
 use actix_web::{
     get, post,
-    web::{Data, Form, Query},
-    HttpResponse, Responder,
+    web::{
+        Data, FormConfig, JsonConfig, PathConfig, Query, QueryConfig,
+        ServiceConfig,
+    },
+    HttpResponse, Responder, ResponseError,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::{postgres::types::PgMoney, PgPool};
 use uuid::Uuid;
 
-use crate::db_api::{
-    self, DeliveryStatistics, Item, Order, OrderStatus, RawMaterial, Shipment,
-    Transformation, TransformationDetails,
+use crate::{
+    db_api::{
+        self, DeliveryStatistics, Item, ItemMovement, Location, Order,
+        OrderStatus, RawMaterial, Shipment, Transformation,
+        TransformationDetails, Warehouse, WarehouseDetails,
+    },
+    error::Error,
 };
 
-fn internal_server_error(e: impl Debug + Display) -> HttpResponse {
-    tracing::error!("{:?}", e);
-    HttpResponse::InternalServerError().body(format!("{e}"))
+use body::Body;
+
+fn error_response(e: impl Into<Error>) -> HttpResponse {
+    let e = e.into();
+    match e {
+        Error::Internal(_) => tracing::error!("{:?}", e),
+        _ => tracing::warn!("{}", e),
+    }
+    e.error_response()
+}
+
+/// Answers a request whose values are not acceptable.
+fn invalid(message: impl Display) -> HttpResponse {
+    error_response(Error::Invalid(message.to_string()))
+}
+
+/// Answers requests that cannot be read with the same JSON body as every
+/// other error.
+pub fn configure_extractor_errors(cfg: &mut ServiceConfig) {
+    fn bad_request(e: impl Display) -> actix_web::Error {
+        Error::BadRequest(e.to_string()).into()
+    }
+
+    cfg.app_data(FormConfig::default().error_handler(|e, _| bad_request(e)))
+        .app_data(JsonConfig::default().error_handler(|e, _| bad_request(e)))
+        .app_data(QueryConfig::default().error_handler(|e, _| bad_request(e)))
+        .app_data(PathConfig::default().error_handler(|e, _| bad_request(e)));
 }
 
-fn bad_request(e: impl Debug + Display) -> HttpResponse {
-    tracing::error!("{:?}", e);
-    HttpResponse::BadRequest().body(format!("{e}"))
+const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct PageQuery {
+    #[serde(default = "PageQuery::first_page")]
+    page: u32,
+    #[serde(default = "PageQuery::default_size")]
+    per_page: u32,
+}
+
+impl PageQuery {
+    fn first_page() -> u32 {
+        1
+    }
+
+    fn default_size() -> u32 {
+        50
+    }
+
+    /// Answers pages that are out of range.
+    fn check(&self) -> Option<HttpResponse> {
+        if self.page == 0 {
+            return Some(invalid("Pages start at 1"));
+        }
+
+        if self.per_page == 0 || self.per_page > MAX_PAGE_SIZE {
+            return Some(invalid(format!(
+                "Page size must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+
+        None
+    }
+
+    fn limit(&self) -> i64 {
+        self.per_page as i64
+    }
+
+    fn offset(&self) -> i64 {
+        (self.page as i64 - 1) * self.per_page as i64
+    }
 }
 
 #[get("/check_health")]
@@ -41,31 +118,40 @@ struct DayForm {
 pub async fn get_date(pool: Data<PgPool>) -> impl Responder {
     let mut con = match pool.acquire().await {
         Ok(con) => con,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     match db_api::get_date(&mut con).await {
         Ok(date) => HttpResponse::Ok().json(DayForm { day: date }),
-        Err(e) => internal_server_error(e),
+        Err(e) => error_response(e),
     }
 }
 
 #[post("/date")]
 pub async fn post_date(
-    form: Form<DayForm>,
+    form: Body<DayForm>,
     pool: Data<PgPool>,
 ) -> impl Responder {
     let mut con = match pool.acquire().await {
         Ok(con) => con,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
-    match db_api::update_date(form.day, &mut con).await {
-        Ok(_) => HttpResponse::Created().finish(),
-        Err(e) => internal_server_error(e),
-    };
+    if let Err(e) = db_api::update_date(form.day, &mut con).await {
+        return error_response(e);
+    }
 
-    HttpResponse::Created().finish()
+    // purchases put off while the warehouses were full are planned again
+    match db_api::NotificationChannel::notify(
+        db_api::NotificationChannel::MaterialsNeeded,
+        &form.day.to_string(),
+        &mut con,
+    )
+    .await
+    {
+        Ok(_) => HttpResponse::Created().finish(),
+        Err(e) => error_response(e),
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -86,7 +172,7 @@ pub async fn get_production(
 ) -> impl Responder {
     let mut tx = match pool.begin().await {
         Ok(tx) => tx,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     let n_items = query.max_n_items as i64;
@@ -94,7 +180,7 @@ pub async fn get_production(
         .await
     {
         Ok(ids) => ids,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     let mut recipes = Vec::new();
@@ -104,7 +190,7 @@ pub async fn get_production(
         while let Some(transf) =
             match TransformationDetails::get_by_id(material, &mut tx).await {
                 Ok(t) => t,
-                Err(e) => return internal_server_error(e),
+                Err(e) => return error_response(e),
             }
         {
             material = transf.product_id;
@@ -114,8 +200,9 @@ pub async fn get_production(
     }
 
     if recipes.iter().any(|r| r.steps.is_empty()) {
-        tracing::error!("Some transformations are missing");
-        return HttpResponse::NotFound().finish();
+        return error_response(Error::NotFound(
+            "Some transformations are missing".to_string(),
+        ));
     }
 
     for recipe in &recipes {
@@ -125,16 +212,16 @@ pub async fn get_production(
             match Order::get_by_item_id(transf.product_id, &mut tx).await {
                 Ok(Some(order)) => order,
                 Ok(None) => continue,
-                Err(e) => return internal_server_error(e),
+                Err(e) => return error_response(e),
             };
         if let Err(e) = order.production_start(&mut tx).await {
-            return internal_server_error(e);
+            return error_response(e);
         }
         tracing::info!("Started production for order {}", order.id());
     }
 
     if let Err(e) = tx.commit().await {
-        return internal_server_error(e);
+        return error_response(e);
     }
 
     HttpResponse::Ok().json(recipes)
@@ -147,14 +234,14 @@ pub async fn get_daily_transformations(
 ) -> impl Responder {
     let mut tx = match pool.begin().await {
         Ok(tx) => tx,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     let day = query.day as i32;
     let tranfs =
         match TransformationDetails::get_pending_by_day(day, &mut tx).await {
             Ok(details) => details,
-            Err(e) => return internal_server_error(e),
+            Err(e) => return error_response(e),
         };
 
     tracing::info!(
@@ -166,7 +253,7 @@ pub async fn get_daily_transformations(
     let mut order_ids = HashSet::new();
     for tf in &tranfs {
         let order = match Order::get_by_item_id(tf.product_id, &mut tx).await {
-            Err(e) => return internal_server_error(e),
+            Err(e) => return error_response(e),
             Ok(Some(order)) => order,
             Ok(None) => {
                 tracing::warn!(
@@ -189,18 +276,26 @@ pub async fn get_daily_transformations(
             }
             OrderStatus::Scheduled => {
                 if let Err(e) = order.production_start(&mut tx).await {
-                    return internal_server_error(e);
+                    return error_response(e);
                 }
             }
             OrderStatus::Producing => continue,
-            _ => todo!("Handle other order statuses"),
+            OrderStatus::Completed
+            | OrderStatus::Delivered
+            | OrderStatus::Canceled => {
+                tracing::warn!(
+                    "Order {} is {} but has pending transformations",
+                    order.id(),
+                    order.status()
+                );
+            }
         }
     }
 
     tracing::info!("Started production for {} orders", order_ids.len());
 
     if let Err(e) = tx.commit().await {
-        return internal_server_error(e);
+        return error_response(e);
     }
 
     HttpResponse::Ok().json(tranfs)
@@ -215,50 +310,80 @@ struct TransfCompletionFrom {
     line_id: String,
     machine_id: String,
     time_taken: i32,
+    // time spent swapping tools before the operation, 0 if no change occurred
+    #[serde(default)]
+    changeover_time: i32,
 }
 
 #[post("/transformations")]
 pub async fn post_transformation_completion(
-    form: Form<TransfCompletionFrom>,
+    form: Body<TransfCompletionFrom>,
     pool: Data<PgPool>,
 ) -> impl Responder {
     let mut tx = match pool.begin().await {
         Ok(tx) => tx,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
+    if let Err(e) =
+        ItemMovement::set_actor("POST /transformations", &mut tx).await
+    {
+        return error_response(e);
+    }
+
     let transf = match Transformation::get_by_id(form.transf_id, &mut tx).await
     {
         Ok(tf) => tf,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     if transf.material_id() != form.material_id {
-        return bad_request("Material id does not match");
+        return invalid("Material id does not match");
     }
 
     if transf.product_id() != form.product_id {
-        return bad_request("Product id does not match");
+        return invalid("Product id does not match");
+    }
+
+    if form.changeover_time < 0 {
+        return invalid("Changeover time cannot be negative");
+    }
+
+    if let Some(planned) = transf.machine() {
+        if planned != form.machine_id {
+            tracing::warn!(
+                "Transformation {} was planned for {} but ran on {}",
+                form.transf_id,
+                planned,
+                form.machine_id
+            );
+        }
     }
 
     let m_query_res = Item::get_by_id(form.material_id, &mut tx).await;
     let p_query_res = Item::get_by_id(form.product_id, &mut tx).await;
     let (material, product) = match (m_query_res, p_query_res) {
         (Ok(material), Ok(product)) => (material, product),
-        (Err(e), _) | (_, Err(e)) => return internal_server_error(e),
+        (Err(e), _) | (_, Err(e)) => return error_response(e),
+    };
+
+    let line = match Location::get(&form.line_id, &mut tx).await {
+        Ok(line) => line,
+        Err(e) => return error_response(e),
     };
 
-    let new_cost = material.get_cost() + PgMoney(form.time_taken as i64 * 100);
-    let p_action_result = product.produce(new_cost, &form.line_id);
+    let machine_time = (form.time_taken + form.changeover_time) as i64;
+    let new_cost = material.get_cost() + PgMoney(machine_time * 100);
+    let p_action_result = product.produce(new_cost, line);
     let m_action_result = material.consume();
     let (product, material) = match (p_action_result, m_action_result) {
         (Ok(p), Ok(m)) => (p, m),
-        (Err(e), _) | (_, Err(e)) => return bad_request(e),
+        (Err(e), _) | (_, Err(e)) => return error_response(e),
     };
 
     let current_date = match db_api::get_date(&mut tx).await {
         Ok(date) => date,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     let tf_result = transf
@@ -267,6 +392,7 @@ pub async fn post_transformation_completion(
             &form.line_id,
             &form.machine_id,
             form.time_taken,
+            form.changeover_time,
             &mut tx,
         )
         .await;
@@ -275,12 +401,12 @@ pub async fn post_transformation_completion(
     let tx_result = match (m_result, p_result, tf_result) {
         (Ok(_), Ok(_), Ok(_)) => tx.commit().await,
         (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
-            return internal_server_error(e)
+            return error_response(e)
         }
     };
 
     if let Err(e) = tx_result {
-        return internal_server_error(e);
+        return error_response(e);
     }
 
     HttpResponse::Created().finish()
@@ -304,36 +430,69 @@ struct WarehouseActionForm {
 
 #[post("/warehouse")]
 pub async fn post_warehouse_action(
-    form: Form<WarehouseActionForm>,
+    form: Body<WarehouseActionForm>,
     pool: Data<PgPool>,
 ) -> impl Responder {
-    let mut connection = match pool.acquire().await {
-        Ok(conn) => conn,
-        Err(e) => return internal_server_error(e),
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
     };
 
-    let item = match Item::get_by_id(form.item_id, &mut connection).await {
+    if let Err(e) = ItemMovement::set_actor("POST /warehouse", &mut tx).await {
+        return error_response(e);
+    }
+
+    let item = match Item::get_by_id(form.item_id, &mut tx).await {
         Ok(item) => item,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
+    };
+
+    let code = match &form.action_type {
+        WarehouseAction::Entry(code) | WarehouseAction::Exit(code) => code,
+    };
+    let location = match Location::get(code, &mut tx).await {
+        Ok(location) => location,
+        Err(e) => return error_response(e),
     };
 
     let item_action_result = match &form.action_type {
-        WarehouseAction::Entry(warehouse_code) => {
-            item.enter_warehouse(warehouse_code)
-        }
-        WarehouseAction::Exit(production_line_code) => {
-            item.exit_warehouse(production_line_code)
+        WarehouseAction::Entry(_) => {
+            if let Location::Warehouse(code) = &location {
+                if let Err(e) = Warehouse::check_free_slot(code, &mut tx).await
+                {
+                    return error_response(e);
+                }
+            }
+            item.enter_warehouse(location)
         }
+        WarehouseAction::Exit(_) => item.exit_warehouse(location),
     };
 
     let item = match item_action_result {
         Ok(item) => item,
-        Err(e) => return bad_request(e),
+        Err(e) => return error_response(e),
     };
 
-    match item.update(&mut connection).await {
+    if let Err(e) = item.update(&mut tx).await {
+        return error_response(e);
+    }
+
+    match tx.commit().await {
         Ok(_) => HttpResponse::Created().finish(),
-        Err(e) => internal_server_error(e),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/warehouses")]
+pub async fn get_warehouses(pool: Data<PgPool>) -> impl Responder {
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    match WarehouseDetails::get_all(&mut con).await {
+        Ok(warehouses) => HttpResponse::Ok().json(warehouses),
+        Err(e) => error_response(e),
     }
 }
 
@@ -351,7 +510,7 @@ pub async fn get_expected_shipments(
 ) -> impl Responder {
     let mut con = match pool.acquire().await {
         Ok(con) => con,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     let expected =
@@ -365,7 +524,7 @@ pub async fn get_expected_shipments(
                 quantity: s.quantity,
             })
             .collect::<Vec<_>>(),
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     HttpResponse::Ok().json(response_body)
@@ -379,22 +538,31 @@ struct ShipmentArrivalForm {
 
 #[post("/materials/arrivals")]
 pub async fn post_material_arrival(
-    form: Form<ShipmentArrivalForm>,
+    form: Body<ShipmentArrivalForm>,
     pool: Data<PgPool>,
 ) -> impl Responder {
-    let date = {
-        let mut con = match pool.acquire().await {
-            Ok(con) => con,
-            Err(e) => return internal_server_error(e),
-        };
-        match db_api::get_date(&mut con).await {
-            Ok(date) => date as i32,
-            Err(e) => return internal_server_error(e),
-        }
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
     };
 
-    match Shipment::arrived(form.shipment_id, date, &pool).await {
-        Err(e) => internal_server_error(e),
+    if let Err(e) =
+        ItemMovement::set_actor("POST /materials/arrivals", &mut tx).await
+    {
+        return error_response(e);
+    }
+
+    let date = match db_api::get_date(&mut tx).await {
+        Ok(date) => date as i32,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = Shipment::arrived(form.shipment_id, date, &mut tx).await {
+        return error_response(e);
+    }
+
+    match tx.commit().await {
+        Err(e) => error_response(e),
         Ok(_) => {
             tracing::info!("Shipment {} arrived", form.shipment_id);
             HttpResponse::Created().finish()
@@ -406,11 +574,11 @@ pub async fn post_material_arrival(
 pub async fn get_deliveries(pool: Data<PgPool>) -> impl Responder {
     let mut con = match pool.acquire().await {
         Ok(con) => con,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
     let deliveries = match Order::get_deliveries(&mut con).await {
         Ok(deliveries) => deliveries,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
     HttpResponse::Ok().json(deliveries)
 }
@@ -423,47 +591,80 @@ struct DeliveryCompletionForm {
 
 #[post("/deliveries")]
 pub async fn post_delivery_confirmation(
-    form: Form<DeliveryCompletionForm>,
+    form: Body<DeliveryCompletionForm>,
     pool: Data<PgPool>,
 ) -> impl Responder {
-    let mut con = match pool.acquire().await {
-        Ok(con) => con,
-        Err(e) => return internal_server_error(e),
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
     };
-    let date = match db_api::get_date(&mut con).await {
+    if let Err(e) = ItemMovement::set_actor("POST /deliveries", &mut tx).await {
+        return error_response(e);
+    }
+    let date = match db_api::get_date(&mut tx).await {
         Ok(date) => date,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
-    match Order::confirm_delivery(&mut con, form.id, date).await {
+    if let Err(e) = Order::confirm_delivery(&mut tx, form.id, date).await {
+        return error_response(e);
+    }
+    match tx.commit().await {
         Ok(_) => HttpResponse::Created().finish(),
-        Err(e) => bad_request(e),
+        Err(e) => error_response(e),
     }
 }
 
 #[post("/deliveries/statistics")]
 pub async fn post_delivery_statistics(
-    form: Form<DeliveryStatistics>,
+    form: Body<DeliveryStatistics>,
     pool: Data<PgPool>,
 ) -> impl Responder {
     let mut con = match pool.acquire().await {
         Ok(con) => con,
-        Err(e) => return internal_server_error(e),
+        Err(e) => return error_response(e),
     };
 
     match form.insert(&mut con).await {
         Ok(_) => HttpResponse::Created().finish(),
-        Err(e) => internal_server_error(e),
+        Err(e) => error_response(e),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{check_health, DayForm};
+    use super::{check_health, configure_extractor_errors, DayForm, PageQuery};
     use crate::{
         configuration::get_configuration,
-        routes::{get_daily_transformations, get_date, post_date},
+        db_api::{
+            self, ClientOrder, FinalPiece, Ingestion, Item, ItemDetails,
+            ItemMovement, ItemStatus, Location, MaterialShipment,
+            OrderTransformation, PieceKind, Shipment, Transformation,
+            TransformationStatus,
+        },
+        routes::{
+            get_daily_transformations, get_date, get_item_history, post_date,
+            post_material_arrival, post_order_cancel, post_warehouse_action,
+        },
+    };
+    use actix_web::{
+        http::{header::ContentType, StatusCode},
+        test,
+        web::Data,
+        App,
     };
-    use actix_web::{test, web::Data, App};
+    use serde_json::{json, Value};
+    use sqlx::{postgres::types::PgMoney, PgConnection};
+
+    #[::core::prelude::v1::test]
+    fn test_page_bounds() {
+        let page = |page, per_page| PageQuery { page, per_page };
+        assert!(page(1, 50).check().is_none());
+        assert!(page(0, 50).check().is_some());
+        assert!(page(1, 0).check().is_some());
+        assert!(page(1, 201).check().is_some());
+        assert_eq!(page(3, 20).limit(), 20);
+        assert_eq!(page(3, 20).offset(), 40);
+    }
 
     #[actix_web::test]
     async fn test_check_health() {
@@ -520,6 +721,36 @@ mod tests {
         assert!(resp.status().is_success())
     }
 
+    #[actix_web::test]
+    async fn test_post_date_json() {
+        let pool = get_configuration()
+            .expect("Failed to read configuration")
+            .database
+            .create_test_db()
+            .await;
+        let app = test::init_service(
+            App::new()
+                .configure(configure_extractor_errors)
+                .service(post_date)
+                .app_data(Data::new(pool)),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/date")
+            .set_json(DayForm { day: 1 })
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let req = test::TestRequest::post()
+            .uri("/date")
+            .insert_header(ContentType::json())
+            .set_payload(r#"{"day": -1}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
     #[actix_web::test]
     async fn test_get_daily_transformations() {
         let pool = get_configuration()
@@ -546,4 +777,275 @@ mod tests {
             panic!("{}: {}", status, body_str);
         }
     }
+
+    #[actix_web::test]
+    async fn test_get_item_history() {
+        let pool = get_configuration()
+            .expect("Failed to read configuration")
+            .database
+            .create_test_db()
+            .await;
+
+        let item = {
+            let mut tx = pool.begin().await.expect("Failed to begin");
+            db_api::update_date(3, &mut tx)
+                .await
+                .expect("Failed to set day");
+            ItemMovement::set_actor("test", &mut tx)
+                .await
+                .expect("Failed to set actor");
+            let line =
+                Location::get("L1", &mut tx).await.expect("Missing line");
+            let item = Item::new(PieceKind::P1)
+                .produce(PgMoney(100), line)
+                .expect("Failed to produce");
+            item.insert(&mut tx).await.expect("Failed to insert");
+            tx.commit().await.expect("Failed to commit");
+            item
+        };
+
+        let app = test::init_service(
+            App::new()
+                .service(post_warehouse_action)
+                .service(get_item_history)
+                .app_data(Data::new(pool.clone())),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/warehouse")
+            .set_json(json!({ "item_id": item.id(), "entry": "W1" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/items/{}/history", item.id()))
+            .to_request();
+        let history: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            history,
+            json!([
+                {
+                    "day": 3,
+                    "actor": "test",
+                    "previous_status": null,
+                    "status": "in_transit",
+                    "previous_location": null,
+                    "location": "L1",
+                },
+                {
+                    "day": 3,
+                    "actor": "POST /warehouse",
+                    "previous_status": "in_transit",
+                    "status": "in_stock",
+                    "previous_location": "L1",
+                    "location": "W1",
+                },
+            ])
+        );
+
+        let mut con = pool.acquire().await.expect("Failed to acquire");
+        for query in [
+            "UPDATE item_movements SET actor = 'other'",
+            "DELETE FROM item_movements",
+        ] {
+            let e = sqlx::query(query)
+                .execute(&mut *con)
+                .await
+                .expect_err("Item movements were changed");
+            assert!(e.to_string().contains("cannot be changed"), "{e}");
+        }
+    }
+
+    /// Inserts a raw material bought in its own shipment, which arrives on
+    /// `arrival` if set.
+    async fn insert_shipped(
+        item: &Item,
+        arrival: Option<i32>,
+        con: &mut PgConnection,
+    ) -> i64 {
+        item.insert(con).await.expect("Failed to insert item");
+        let shipment = Shipment::new(1, 0, 1, PgMoney(3000))
+            .insert(con)
+            .await
+            .expect("Failed to insert shipment");
+        MaterialShipment::new(item.id(), shipment)
+            .insert(con)
+            .await
+            .expect("Failed to link shipment");
+        if let Some(day) = arrival {
+            Shipment::arrived(shipment, day, con)
+                .await
+                .expect("Failed to record arrival");
+        }
+        shipment
+    }
+
+    #[actix_web::test]
+    async fn test_post_order_cancel() {
+        let pool = get_configuration()
+            .expect("Failed to read configuration")
+            .database
+            .create_test_db()
+            .await;
+
+        let order = ClientOrder::new(
+            "Client".to_string(),
+            1,
+            FinalPiece::P5,
+            1,
+            30,
+            0,
+            0,
+        );
+        let Ok(Ingestion::Inserted(order_id)) = order.insert_to_db(&pool).await
+        else {
+            panic!("Failed to insert order");
+        };
+
+        let mut con = pool.acquire().await.expect("Failed to acquire");
+        let arrived = Item::new(PieceKind::P1).set_order(Some(order_id));
+        insert_shipped(&arrived, Some(2), &mut con).await;
+        let pending = Item::new(PieceKind::P1).set_order(Some(order_id));
+        let pending_shipment = insert_shipped(&pending, None, &mut con).await;
+        let product = Item::new(PieceKind::P3).set_order(Some(order_id));
+        product
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert item");
+        Transformation::new(product.id(), arrived.id(), 1)
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert transformation");
+
+        let app = test::init_service(
+            App::new()
+                .service(post_order_cancel)
+                .app_data(Data::new(pool.clone())),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri(&format!("/orders/{}/cancel", order_id))
+            .set_json(json!({ "reason": "test" }))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["released_items"], 2);
+        assert_eq!(body["released_shipment_slots"], 1);
+
+        // the arrived raw material is free stock, the pending one is not
+        // bought anymore and the product stays with the canceled order
+        for (item, order) in [
+            (arrived.id(), None),
+            (pending.id(), None),
+            (product.id(), Some(order_id)),
+        ] {
+            let item =
+                Item::get_by_id(item, &mut con).await.expect("Missing item");
+            assert_eq!(item.order_id(), order);
+        }
+        let slots =
+            MaterialShipment::count_by_shipment_id(pending_shipment, &mut con)
+                .await
+                .expect("Failed to count shipment slots");
+        assert_eq!(slots, 0);
+
+        // the free stock is allocated to the next order under a new id, the
+        // canceled transformation is kept and follows it
+        let next_order = ClientOrder {
+            order_number: 2,
+            ..order
+        };
+        let Ok(Ingestion::Inserted(next_order_id)) =
+            next_order.insert_to_db(&pool).await
+        else {
+            panic!("Failed to insert order");
+        };
+        let material = Item::new(PieceKind::P1).set_order(Some(next_order_id));
+        material
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert item");
+        let material = ItemDetails::get_by_id(material.id(), &mut con)
+            .await
+            .expect("Missing item");
+        assert_eq!(material.status, ItemStatus::InStock);
+
+        let transformations =
+            OrderTransformation::get_by_order(order_id, &mut con)
+                .await
+                .expect("Failed to get transformations");
+        assert_eq!(transformations.len(), 1);
+        assert_eq!(transformations[0].status, TransformationStatus::Canceled);
+        assert_eq!(transformations[0].material_id, material.id);
+    }
+
+    #[actix_web::test]
+    async fn test_post_material_arrival_when_full() {
+        let pool = get_configuration()
+            .expect("Failed to read configuration")
+            .database
+            .create_test_db()
+            .await;
+
+        let mut con = pool.acquire().await.expect("Failed to acquire");
+        sqlx::query("UPDATE warehouses SET capacity = 1")
+            .execute(&mut *con)
+            .await
+            .expect("Failed to set capacity");
+        sqlx::query(
+            "INSERT INTO items (piece_kind, status, location)
+            VALUES ('P1', 'in_stock', 'W1'), ('P1', 'in_stock', 'W2')",
+        )
+        .execute(&mut *con)
+        .await
+        .expect("Failed to fill warehouses");
+
+        // one item was bought, the other one comes for free
+        let item = Item::new(PieceKind::P1);
+        item.insert(&mut con).await.expect("Failed to insert item");
+        let shipment = Shipment::new(1, 0, 2, PgMoney(6000))
+            .insert(&mut con)
+            .await
+            .expect("Failed to insert shipment");
+        MaterialShipment::new(item.id(), shipment)
+            .insert(&mut con)
+            .await
+            .expect("Failed to link shipment");
+
+        let app = test::init_service(
+            App::new()
+                .service(post_material_arrival)
+                .service(post_warehouse_action)
+                .app_data(Data::new(pool.clone())),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/materials/arrivals")
+            .set_json(json!({ "shipment_id": shipment }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // the arrival is recorded and both items wait at the dock
+        let item = ItemDetails::get_by_id(item.id(), &mut con)
+            .await
+            .expect("Missing item");
+        assert_eq!(item.status, ItemStatus::InTransit);
+        assert_eq!(item.location, None);
+        let (waiting,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM items
+            WHERE status = 'in_transit' AND location IS NULL",
+        )
+        .fetch_one(&mut *con)
+        .await
+        .expect("Failed to count items");
+        assert_eq!(waiting, 2);
+
+        let req = test::TestRequest::post()
+            .uri("/warehouse")
+            .set_json(json!({ "item_id": item.id, "entry": "W1" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
 }
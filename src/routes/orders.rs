@@ -0,0 +1,322 @@
+use actix_web::{
+    get,
+    guard::GuardContext,
+    http::header::ContentType,
+    post,
+    web::{Data, Json, Path, Query},
+    HttpResponse, Responder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    db_api::{
+        ClientOrder, CostReport, Item, ItemDetails, MaterialShipment, Order,
+        OrderConflict, OrderCost, OrderFilter, OrderSummary,
+        OrderTransformation, ProfitReport, Transformation,
+    },
+    error::Error,
+    intake::Intake,
+};
+
+use super::{error_response, invalid, Body, PageQuery};
+
+#[derive(Debug, Serialize)]
+struct OrderPage {
+    page: u32,
+    per_page: u32,
+    total: i64,
+    orders: Vec<OrderSummary>,
+}
+
+#[derive(Debug, Serialize)]
+struct OrderDetails {
+    #[serde(flatten)]
+    order: OrderSummary,
+    items: Vec<ItemDetails>,
+    transformations: Vec<OrderTransformation>,
+}
+
+#[get("/orders")]
+pub async fn get_orders(
+    filter: Query<OrderFilter>,
+    page: Query<PageQuery>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    if let Some(response) = page.check() {
+        return response;
+    }
+
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    let total = match OrderSummary::count_filtered(&filter, &mut con).await {
+        Ok(total) => total,
+        Err(e) => return error_response(e),
+    };
+
+    let orders = match OrderSummary::get_filtered(
+        &filter,
+        page.limit(),
+        page.offset(),
+        &mut con,
+    )
+    .await
+    {
+        Ok(orders) => orders,
+        Err(e) => return error_response(e),
+    };
+
+    HttpResponse::Ok().json(OrderPage {
+        page: page.page,
+        per_page: page.per_page,
+        total,
+        orders,
+    })
+}
+
+#[get("/orders/{id}")]
+pub async fn get_order(path: Path<Uuid>, pool: Data<PgPool>) -> impl Responder {
+    let order_id = path.into_inner();
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    let order = match OrderSummary::get_by_id(order_id, &mut con).await {
+        Ok(Some(order)) => order,
+        Ok(None) => {
+            return error_response(Error::not_found(format!(
+                "Order {}",
+                order_id
+            )))
+        }
+        Err(e) => return error_response(e),
+    };
+
+    let items = match ItemDetails::get_by_order(order_id, &mut con).await {
+        Ok(items) => items,
+        Err(e) => return error_response(e),
+    };
+
+    let transformations =
+        match OrderTransformation::get_by_order(order_id, &mut con).await {
+            Ok(transformations) => transformations,
+            Err(e) => return error_response(e),
+        };
+
+    HttpResponse::Ok().json(OrderDetails {
+        order,
+        items,
+        transformations,
+    })
+}
+
+/// Orders sent as JSON, either a single order or a list of orders.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ClientOrders {
+    One(ClientOrder),
+    Many(Vec<ClientOrder>),
+}
+
+fn is_json(ctx: &GuardContext) -> bool {
+    ctx.header::<ContentType>()
+        .is_some_and(|ct| ct.subtype() == "json")
+}
+
+/// Receives orders the same way the UDP listener does, replying with the
+/// acknowledgement of each order as JSON.
+#[post("/orders", guard = "is_json")]
+pub async fn post_orders_json(
+    body: Json<ClientOrders>,
+    intake: Data<Intake>,
+) -> impl Responder {
+    let orders = match body.into_inner() {
+        ClientOrders::One(order) => vec![order],
+        ClientOrders::Many(orders) => orders,
+    };
+
+    // the acknowledgement names the client only when there is a single one
+    let client = orders
+        .first()
+        .map(|o| o.client_name.clone())
+        .filter(|c| orders.iter().all(|o| &o.client_name == c));
+
+    let ack = intake.receive(client, orders, Vec::new()).await;
+    HttpResponse::Ok().json(ack)
+}
+
+/// Receives an XML command, as sent to the UDP listener, and replies with
+/// its XML acknowledgement.
+#[post("/orders")]
+pub async fn post_orders_xml(
+    body: String,
+    intake: Data<Intake>,
+) -> impl Responder {
+    let ack = intake.receive_xml(&body).await;
+    let mut response = match ack.error {
+        Some(_) => HttpResponse::BadRequest(),
+        None => HttpResponse::Ok(),
+    };
+    response.content_type(ContentType::xml()).body(ack.to_xml())
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct CancelForm {
+    reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Cancellation {
+    order_id: Uuid,
+    canceled_transformations: u64,
+    released_items: u64,
+    released_shipment_slots: u64,
+}
+
+/// Orders received again with different content, for an operator to review.
+#[get("/order_conflicts")]
+pub async fn get_order_conflicts(pool: Data<PgPool>) -> impl Responder {
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    match OrderConflict::get_all(&mut con).await {
+        Ok(conflicts) => HttpResponse::Ok().json(conflicts),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/orders/{id}/cost")]
+pub async fn get_order_cost(
+    path: Path<Uuid>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    let order_id = path.into_inner();
+    match OrderCost::get_by_order(order_id, &mut con).await {
+        Ok(Some(cost)) => HttpResponse::Ok().json(cost),
+        Ok(None) => {
+            error_response(Error::not_found(format!("Order {}", order_id)))
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/reports/costs")]
+pub async fn get_cost_report(pool: Data<PgPool>) -> impl Responder {
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    match CostReport::delivered(&mut con).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => error_response(e),
+    }
+}
+
+#[get("/reports/profits")]
+pub async fn get_profit_report(pool: Data<PgPool>) -> impl Responder {
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    match ProfitReport::delivered(&mut con).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Cancels an order that was not delivered yet.
+///
+/// Pending transformations are canceled, the order's items in stock become
+/// free stock and its raw materials are unlinked from shipments that have
+/// not arrived, so that they can be used by other orders. Those raw materials
+/// are released as well and are never bought.
+///
+/// Items on a production line and the intermediate and final pieces that
+/// were not produced stay with the canceled order, next to its canceled
+/// transformations.
+#[post("/orders/{id}/cancel")]
+pub async fn post_order_cancel(
+    path: Path<Uuid>,
+    form: Body<CancelForm>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    let order_id = path.into_inner();
+    let reason = form.reason.trim();
+    if reason.is_empty() {
+        return invalid("A cancellation reason is required");
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    let order = match Order::get_by_id(order_id, &mut tx).await {
+        Ok(order) => order,
+        Err(e) => return error_response(e),
+    };
+
+    if !order.can_cancel() {
+        return error_response(Error::Conflict(format!(
+            "Order {} is {}",
+            order_id,
+            order.status()
+        )));
+    }
+
+    match order.cancel(reason, &mut tx).await {
+        Ok(res) if res.rows_affected() == 0 => {
+            return error_response(Error::Conflict(format!(
+                "Order {} changed, try again",
+                order_id
+            )))
+        }
+        Ok(_) => (),
+        Err(e) => return error_response(e),
+    }
+
+    let canceled_transformations =
+        match Transformation::cancel_by_order(order_id, &mut tx).await {
+            Ok(n) => n,
+            Err(e) => return error_response(e),
+        };
+
+    let released_shipment_slots =
+        match MaterialShipment::delete_pending_by_order(order_id, &mut tx).await
+        {
+            Ok(n) => n,
+            Err(e) => return error_response(e),
+        };
+
+    let released_items = match Item::release_by_order(order_id, &mut tx).await {
+        Ok(n) => n,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = tx.commit().await {
+        return error_response(e);
+    }
+
+    tracing::info!("Canceled order {}: {}", order_id, reason);
+    HttpResponse::Ok().json(Cancellation {
+        order_id,
+        canceled_transformations,
+        released_items,
+        released_shipment_slots,
+    })
+}
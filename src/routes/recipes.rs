@@ -0,0 +1,266 @@
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Path},
+    HttpResponse, Responder,
+};
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    db_api::{PieceKind, Recipe, ToolType},
+    error::Error,
+    scheduler::RecipeGraph,
+};
+
+use super::{error_response, invalid, Body};
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct RecipeForm {
+    material_kind: PieceKind,
+    product_kind: PieceKind,
+    tool: ToolType,
+    operation_time: i64,
+}
+
+impl RecipeForm {
+    fn into_recipe(self) -> Result<Recipe, Error> {
+        if self.operation_time <= 0 {
+            return Err(Error::Invalid(
+                "Operation time must be positive".to_string(),
+            ));
+        }
+
+        // stored as an int
+        let operation_time =
+            i32::try_from(self.operation_time).map_err(|_| {
+                Error::Invalid(format!(
+                    "Operation time cannot exceed {} seconds",
+                    i32::MAX
+                ))
+            })?;
+
+        Ok(Recipe {
+            id: 0, // assigned by the database
+            material_kind: self.material_kind,
+            product_kind: self.product_kind,
+            tool: self.tool,
+            operation_time,
+        })
+    }
+}
+
+async fn check_recipe(
+    recipe: &Recipe,
+    con: &mut PgConnection,
+) -> Result<(), HttpResponse> {
+    if recipe.material_kind == recipe.product_kind {
+        return Err(invalid("Material and product must differ"));
+    }
+
+    match recipe.tool.exists(con).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            Err(invalid(format!("Tool {:?} does not exist", recipe.tool)))
+        }
+        Err(e) => Err(error_response(e)),
+    }
+}
+
+/// Checks that the recipes in use still form a valid recipe graph.
+///
+/// Meant to be called after the edit was applied inside a transaction, so
+/// that the graph being validated already includes it.
+async fn check_graph(con: &mut PgConnection) -> Result<(), HttpResponse> {
+    let recipes = match Recipe::get_all(con).await {
+        Ok(recipes) => recipes,
+        Err(e) => return Err(error_response(e)),
+    };
+
+    match RecipeGraph::new(recipes) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(invalid(e)),
+    }
+}
+
+async fn insert(
+    recipe: &Recipe,
+    con: &mut PgConnection,
+) -> Result<i64, HttpResponse> {
+    match recipe.insert(con).await {
+        Ok(id) => Ok(id),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            Err(error_response(Error::Conflict(
+                "A recipe with the same material, product and tool exists"
+                    .to_string(),
+            )))
+        }
+        Err(e) => Err(error_response(e)),
+    }
+}
+
+#[get("/recipes")]
+pub async fn get_recipes(pool: Data<PgPool>) -> impl Responder {
+    let mut con = match pool.acquire().await {
+        Ok(con) => con,
+        Err(e) => return error_response(e),
+    };
+
+    match Recipe::get_all(&mut con).await {
+        Ok(recipes) => HttpResponse::Ok().json(recipes),
+        Err(e) => error_response(e),
+    }
+}
+
+#[post("/recipes")]
+pub async fn post_recipe(
+    form: Body<RecipeForm>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    let mut recipe = match form.into_inner().into_recipe() {
+        Ok(recipe) => recipe,
+        Err(e) => return error_response(e),
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = Recipe::lock(&mut tx).await {
+        return error_response(e);
+    }
+
+    if let Err(response) = check_recipe(&recipe, &mut tx).await {
+        return response;
+    }
+
+    recipe.id = match insert(&recipe, &mut tx).await {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+
+    if let Err(response) = check_graph(&mut tx).await {
+        return response;
+    }
+
+    if let Err(e) = tx.commit().await {
+        return error_response(e);
+    }
+
+    tracing::info!("Added recipe {}", recipe.id);
+    HttpResponse::Created().json(recipe)
+}
+
+/// Replaces a recipe with a new version.
+///
+/// The old recipe is retired rather than changed, so transformations already
+/// planned with it are not affected. The new version gets a new id.
+#[put("/recipes/{id}")]
+pub async fn put_recipe(
+    path: Path<i64>,
+    form: Body<RecipeForm>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let mut recipe = match form.into_inner().into_recipe() {
+        Ok(recipe) => recipe,
+        Err(e) => return error_response(e),
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = Recipe::lock(&mut tx).await {
+        return error_response(e);
+    }
+
+    match Recipe::retire(id, &mut tx).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return error_response(Error::not_found(format!("Recipe {}", id)))
+        }
+        Err(e) => return error_response(e),
+    }
+
+    if let Err(response) = check_recipe(&recipe, &mut tx).await {
+        return response;
+    }
+
+    recipe.id = match insert(&recipe, &mut tx).await {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+
+    if let Err(response) = check_graph(&mut tx).await {
+        return response;
+    }
+
+    if let Err(e) = tx.commit().await {
+        return error_response(e);
+    }
+
+    tracing::info!("Replaced recipe {} with {}", id, recipe.id);
+    HttpResponse::Ok().json(recipe)
+}
+
+#[delete("/recipes/{id}")]
+pub async fn delete_recipe(
+    path: Path<i64>,
+    pool: Data<PgPool>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return error_response(e),
+    };
+
+    if let Err(e) = Recipe::lock(&mut tx).await {
+        return error_response(e);
+    }
+
+    match Recipe::retire(id, &mut tx).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return error_response(Error::not_found(format!("Recipe {}", id)))
+        }
+        Err(e) => return error_response(e),
+    }
+
+    if let Err(response) = check_graph(&mut tx).await {
+        return response;
+    }
+
+    if let Err(e) = tx.commit().await {
+        return error_response(e);
+    }
+
+    tracing::info!("Retired recipe {}", id);
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(operation_time: i64) -> RecipeForm {
+        RecipeForm {
+            material_kind: PieceKind::P1,
+            product_kind: PieceKind::P3,
+            tool: ToolType::T1,
+            operation_time,
+        }
+    }
+
+    #[test]
+    fn operation_time_fits_the_recipes_column() {
+        assert_eq!(form(45).into_recipe().unwrap().operation_time, 45);
+        assert!(matches!(form(0).into_recipe(), Err(Error::Invalid(_))));
+        assert!(matches!(
+            form(i64::from(i32::MAX) + 1).into_recipe(),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
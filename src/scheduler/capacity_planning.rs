@@ -0,0 +1,538 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use sqlx::PgConnection;
+
+use crate::db_api::{Item, Machine, MachineLoad, Recipe, ToolType};
+
+use super::handlers::{blueprint_handler::ItemBlueprint, item_handler::Step};
+
+#[derive(Debug, Clone)]
+struct Booking {
+    day: i32,
+    machine: String,
+    tool: ToolType,
+    time: i64,
+}
+
+/// Finite capacity view of the shop floor.
+///
+/// Keeps track of how much production time is booked on each machine for
+/// each day, and with which tools, so that new transformations are only
+/// placed where there is still room for them and same-tool work ends up
+/// grouped on the same machine.
+#[derive(Debug, Clone)]
+pub struct CapacityPlan {
+    machines: Vec<Machine>,
+    booked: HashMap<(i32, String), HashMap<ToolType, i64>>,
+    earliest_start: i32,
+    logistics_factor: i64,
+}
+
+impl CapacityPlan {
+    pub fn new(
+        machines: Vec<Machine>,
+        loads: Vec<MachineLoad>,
+        earliest_start: i32,
+        logistics_factor: i64,
+    ) -> Self {
+        let mut plan = Self {
+            machines,
+            booked: HashMap::new(),
+            earliest_start,
+            logistics_factor,
+        };
+
+        for load in loads {
+            let time = plan.effective_time(load.booked_time);
+            plan.book(load.date, &load.machine, load.tool, time);
+        }
+
+        plan
+    }
+
+    pub async fn load(
+        machines: Vec<Machine>,
+        earliest_start: i32,
+        logistics_factor: i64,
+        con: &mut PgConnection,
+    ) -> sqlx::Result<Self> {
+        let loads = MachineLoad::get_from_day(earliest_start, con).await?;
+        Ok(Self::new(machines, loads, earliest_start, logistics_factor))
+    }
+
+    // assume a % of the needed time is spent on logistics instead of production
+    fn effective_time(&self, operation_time: i64) -> i64 {
+        operation_time + operation_time * self.logistics_factor / 100
+    }
+
+    fn booked_tools(
+        &self,
+        day: i32,
+        machine: &Machine,
+    ) -> impl Iterator<Item = (&ToolType, &i64)> {
+        self.booked
+            .get(&(day, machine.code().to_string()))
+            .into_iter()
+            .flatten()
+            .filter(|(_, time)| **time > 0)
+    }
+
+    fn tools_in_use(&self, day: i32, machine: &Machine) -> Vec<ToolType> {
+        self.booked_tools(day, machine).map(|(t, _)| *t).collect()
+    }
+
+    fn operation_time(&self, day: i32, machine: &Machine) -> i64 {
+        self.booked_tools(day, machine).map(|(_, time)| time).sum()
+    }
+
+    // tools are assumed to be worked through one after the other, in the
+    // order that needs the least changeover time
+    fn booked_time(&self, day: i32, machine: &Machine) -> i64 {
+        let tools = self.tools_in_use(day, machine);
+        self.operation_time(day, machine) + machine.sequence_changeover(&tools)
+    }
+
+    fn free_time(&self, day: i32, machine: &Machine) -> i64 {
+        machine.day_capacity() - self.booked_time(day, machine)
+    }
+
+    /// Extra changeover time needed to also work with `tool` on `day`.
+    fn added_changeover(
+        &self,
+        day: i32,
+        machine: &Machine,
+        tool: ToolType,
+    ) -> i64 {
+        let mut tools = self.tools_in_use(day, machine);
+        if tools.contains(&tool) {
+            return 0;
+        }
+        let before = machine.sequence_changeover(&tools);
+        tools.push(tool);
+        machine.sequence_changeover(&tools) - before
+    }
+
+    // an idle machine can always take one operation, even if it spills over
+    // the end of the day
+    fn fits(
+        &self,
+        day: i32,
+        machine: &Machine,
+        tool: ToolType,
+        time: i64,
+    ) -> bool {
+        let needed = time + self.added_changeover(day, machine, tool);
+        self.free_time(day, machine) >= needed
+            || self.operation_time(day, machine) == 0
+    }
+
+    fn book(&mut self, day: i32, machine: &str, tool: ToolType, time: i64) {
+        *self
+            .booked
+            .entry((day, machine.to_string()))
+            .or_default()
+            .entry(tool)
+            .or_insert(0) += time;
+    }
+
+    fn unbook(&mut self, booking: &Booking) {
+        self.book(booking.day, &booking.machine, booking.tool, -booking.time);
+    }
+
+    /// Eligible machine for `tool` on `day`.
+    ///
+    /// Machines that already have the tool mounted that day come first, then
+    /// the ones that need the least extra changeover time and have the
+    /// fewest different tools booked, so that same-tool work is grouped and
+    /// tool changes are kept to a minimum. Ties are broken by the most free
+    /// time left.
+    fn find_machine(
+        &self,
+        day: i32,
+        tool: ToolType,
+        time: i64,
+    ) -> Option<String> {
+        self.machines
+            .iter()
+            .filter(|m| m.can_use(tool) && self.fits(day, m, tool, time))
+            .max_by_key(|m| {
+                let tools = self.tools_in_use(day, m);
+                (
+                    tools.contains(&tool),
+                    Reverse(self.added_changeover(day, m, tool)),
+                    Reverse(tools.len()),
+                    self.free_time(day, m),
+                    Reverse(m.code()),
+                )
+            })
+            .map(|m| m.code().to_string())
+    }
+
+    fn assign(steps: &mut [Step], bookings: Vec<Booking>) {
+        for (step, booking) in steps.iter_mut().zip(bookings) {
+            step.transf.set_date(booking.day);
+            step.transf.set_machine(booking.machine);
+        }
+    }
+
+    /// Books the steps of an item as late as possible, finishing no later
+    /// than `completion_date`.
+    ///
+    /// Steps are expected in the order produced by `describe_process`, i.e.
+    /// starting with the transformation that yields the final item.
+    /// Returns the day on which the item is finished, or `None` if it does
+    /// not fit between the earliest start and the completion date, in which
+    /// case nothing is booked.
+    pub fn schedule_backward(
+        &mut self,
+        steps: &mut [Step],
+        completion_date: i32,
+    ) -> Option<i32> {
+        let mut latest = completion_date;
+        let mut bookings = Vec::with_capacity(steps.len());
+
+        for step in steps.iter() {
+            let time = self.effective_time(step.recipe.operation_time.into());
+            let slot = (self.earliest_start..=latest).rev().find_map(|day| {
+                self.find_machine(day, step.recipe.tool, time)
+                    .map(|machine| (day, machine))
+            });
+
+            let Some((day, machine)) = slot else {
+                bookings.iter().for_each(|b| self.unbook(b));
+                return None;
+            };
+
+            let tool = step.recipe.tool;
+            self.book(day, &machine, tool, time);
+            bookings.push(Booking {
+                day,
+                machine,
+                tool,
+                time,
+            });
+            latest = day;
+        }
+
+        let finish = bookings.first().map(|b| b.day);
+        Self::assign(steps, bookings);
+        finish
+    }
+
+    /// Books the steps of an item as soon as possible, starting on the
+    /// earliest start day. Used when an item cannot be finished on time.
+    ///
+    /// Returns the day on which the item is finished.
+    pub fn schedule_forward(
+        &mut self,
+        steps: &mut [Step],
+    ) -> anyhow::Result<i32> {
+        let mut earliest = self.earliest_start;
+        let mut bookings = Vec::with_capacity(steps.len());
+
+        for step in steps.iter().rev() {
+            let time = self.effective_time(step.recipe.operation_time.into());
+            if !self.machines.iter().any(|m| m.can_use(step.recipe.tool)) {
+                bookings.iter().for_each(|b| self.unbook(b));
+                anyhow::bail!(
+                    "No machine can use tool {:?} for recipe {}",
+                    step.recipe.tool,
+                    step.recipe.id,
+                );
+            }
+
+            let (day, machine) = (earliest..)
+                .find_map(|day| {
+                    self.find_machine(day, step.recipe.tool, time)
+                        .map(|machine| (day, machine))
+                })
+                .expect("an idle machine can always take an operation");
+
+            let tool = step.recipe.tool;
+            self.book(day, &machine, tool, time);
+            bookings.push(Booking {
+                day,
+                machine,
+                tool,
+                time,
+            });
+            earliest = day;
+        }
+
+        bookings.reverse();
+        let finish = bookings.first().map_or(self.earliest_start, |b| b.day);
+        Self::assign(steps, bookings);
+        Ok(finish)
+    }
+
+    /// Schedules `item` through whichever of its alternative `routes` fits
+    /// the current load best.
+    ///
+    /// Routes that can be finished by `completion_date` are preferred and,
+    /// among those, the one that can start the latest, leaving earlier days
+    /// free for other work. When no route is on time, the one that finishes
+    /// first is used. Ties keep the order of `routes`, so with an idle shop
+    /// floor the first route is always the one picked.
+    ///
+    /// Returns the scheduled blueprint and the day on which it is finished.
+    pub fn schedule_item(
+        &mut self,
+        item: &Item,
+        routes: &[Vec<Recipe>],
+        completion_date: i32,
+    ) -> anyhow::Result<(ItemBlueprint, i32)> {
+        let mut best: Option<((bool, i32), i32, ItemBlueprint, Self)> = None;
+
+        for route in routes {
+            let mut bp = match ItemBlueprint::generate(item.clone(), route) {
+                Ok(bp) => bp,
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    continue;
+                }
+            };
+
+            let mut trial = self.clone();
+            let (score, finish) = match trial
+                .schedule_backward(bp.process_mut(), completion_date)
+            {
+                Some(finish) => {
+                    let start = bp.start_date().unwrap_or(finish);
+                    ((true, start), finish)
+                }
+                None => match trial.schedule_forward(bp.process_mut()) {
+                    Ok(finish) => ((false, -finish), finish),
+                    Err(e) => {
+                        tracing::warn!("{:?}", e);
+                        continue;
+                    }
+                },
+            };
+
+            match &best {
+                Some((best_score, ..)) if score <= *best_score => (),
+                _ => best = Some((score, finish, bp, trial)),
+            }
+        }
+
+        let Some((_, finish, bp, trial)) = best else {
+            anyhow::bail!("No recipe route can produce item {}", item.id());
+        };
+
+        *self = trial;
+        Ok((bp, finish))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db_api::{Item, PieceKind, Recipe},
+        scheduler::handlers::item_handler::describe_process,
+    };
+
+    fn recipe(
+        id: i64,
+        material_kind: PieceKind,
+        product_kind: PieceKind,
+        tool: ToolType,
+        operation_time: i32,
+    ) -> Recipe {
+        Recipe {
+            id,
+            material_kind,
+            product_kind,
+            tool,
+            operation_time,
+        }
+    }
+
+    fn p5_recipe() -> Vec<Recipe> {
+        vec![
+            recipe(4, PieceKind::P4, PieceKind::P5, ToolType::T4, 25),
+            recipe(2, PieceKind::P3, PieceKind::P4, ToolType::T2, 15),
+            recipe(1, PieceKind::P1, PieceKind::P3, ToolType::T1, 45),
+        ]
+    }
+
+    fn machines() -> Vec<Machine> {
+        vec![
+            Machine::new("M1", 60, vec![ToolType::T1, ToolType::T2]),
+            Machine::new("M3", 60, vec![ToolType::T1, ToolType::T4]),
+        ]
+    }
+
+    fn steps() -> Vec<Step> {
+        describe_process(&p5_recipe(), Item::new(PieceKind::P5)).unwrap()
+    }
+
+    fn bookings(steps: &[Step]) -> Vec<(Option<i32>, Option<String>)> {
+        steps
+            .iter()
+            .map(|s| (s.transf.date(), s.transf.machine().map(String::from)))
+            .collect()
+    }
+
+    #[test]
+    fn schedules_backward_from_completion_date() {
+        let mut plan = CapacityPlan::new(machines(), vec![], 2, 0);
+        let mut steps = steps();
+
+        let finish = plan.schedule_backward(&mut steps, 5);
+
+        assert_eq!(finish, Some(5));
+        assert_eq!(
+            bookings(&steps),
+            vec![
+                (Some(5), Some("M3".to_string())),
+                (Some(5), Some("M1".to_string())),
+                (Some(5), Some("M1".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn respects_existing_bookings() {
+        let loads = vec![MachineLoad {
+            machine: "M3".to_string(),
+            date: 5,
+            tool: ToolType::T1,
+            booked_time: 50,
+        }];
+        let mut plan = CapacityPlan::new(machines(), loads, 2, 0);
+        let mut steps = steps();
+
+        let finish = plan.schedule_backward(&mut steps, 5);
+
+        assert_eq!(finish, Some(4));
+        assert_eq!(steps[0].transf.date(), Some(4));
+    }
+
+    #[test]
+    fn groups_same_tool_work() {
+        let machines = vec![
+            Machine::new("M1", 60, vec![ToolType::T1, ToolType::T2]),
+            Machine::new("M2", 60, vec![ToolType::T1, ToolType::T2]),
+        ];
+        let loads = vec![MachineLoad {
+            machine: "M2".to_string(),
+            date: 5,
+            tool: ToolType::T2,
+            booked_time: 15,
+        }];
+        let plan = CapacityPlan::new(machines, loads, 2, 0);
+
+        assert_eq!(
+            plan.find_machine(5, ToolType::T2, 15).as_deref(),
+            Some("M2")
+        );
+        assert_eq!(
+            plan.find_machine(5, ToolType::T1, 45).as_deref(),
+            Some("M1")
+        );
+    }
+
+    #[test]
+    fn accounts_for_tool_changeovers() {
+        let machines =
+            vec![Machine::new("M1", 60, vec![ToolType::T1, ToolType::T2])
+                .with_changeover(ToolType::T1, ToolType::T2, 10)
+                .with_changeover(ToolType::T2, ToolType::T1, 5)];
+        let loads = vec![MachineLoad {
+            machine: "M1".to_string(),
+            date: 5,
+            tool: ToolType::T2,
+            booked_time: 40,
+        }];
+        let plan = CapacityPlan::new(machines, loads, 2, 0);
+
+        assert_eq!(
+            plan.find_machine(5, ToolType::T2, 20).as_deref(),
+            Some("M1")
+        );
+        assert_eq!(plan.find_machine(5, ToolType::T1, 20), None);
+        assert_eq!(
+            plan.find_machine(5, ToolType::T1, 15).as_deref(),
+            Some("M1")
+        );
+    }
+
+    #[test]
+    fn picks_route_around_saturated_tools() {
+        let machines = vec![
+            Machine::new("M1", 60, vec![ToolType::T1, ToolType::T2]),
+            Machine::new("M2", 60, vec![ToolType::T3]),
+            Machine::new("M3", 60, vec![ToolType::T1, ToolType::T4]),
+        ];
+        let t2_route = p5_recipe();
+        let mut t3_route = p5_recipe();
+        t3_route[1] = recipe(3, PieceKind::P3, PieceKind::P4, ToolType::T3, 25);
+        let routes = vec![t2_route, t3_route];
+        let item = Item::new(PieceKind::P5);
+
+        let mut plan = CapacityPlan::new(machines.clone(), vec![], 2, 0);
+        let (bp, _) = plan.schedule_item(&item, &routes, 5).unwrap();
+        assert_eq!(bp.start_date(), Some(5));
+        assert_eq!(bp.process()[1].recipe.id, 2);
+
+        let loads = (2..=5)
+            .map(|date| MachineLoad {
+                machine: "M1".to_string(),
+                date,
+                tool: ToolType::T2,
+                booked_time: 60,
+            })
+            .collect();
+        let mut plan = CapacityPlan::new(machines, loads, 2, 0);
+        let (bp, finish) = plan.schedule_item(&item, &routes, 5).unwrap();
+        assert_eq!(finish, 5);
+        assert_eq!(bp.process()[1].recipe.id, 3);
+    }
+
+    #[test]
+    fn falls_back_to_forward_scheduling() {
+        let mut plan = CapacityPlan::new(machines(), vec![], 2, 0);
+        let mut first = steps();
+        let mut second = steps();
+
+        assert_eq!(plan.schedule_backward(&mut first, 2), Some(2));
+        assert_eq!(plan.schedule_backward(&mut second, 2), None);
+        assert!(bookings(&second).iter().all(|b| b.0.is_none()));
+
+        let finish = plan.schedule_forward(&mut second).unwrap();
+        assert_eq!(finish, 3);
+        assert_eq!(second.last().unwrap().transf.date(), Some(3));
+    }
+
+    #[test]
+    fn idle_machines_take_long_operations() {
+        let mut plan = CapacityPlan::new(machines(), vec![], 2, 50);
+        let mut steps = steps();
+
+        assert_eq!(plan.schedule_backward(&mut steps, 2), None);
+        assert_eq!(plan.schedule_forward(&mut steps).unwrap(), 3);
+        assert_eq!(
+            bookings(&steps),
+            vec![
+                (Some(3), Some("M3".to_string())),
+                (Some(3), Some("M1".to_string())),
+                (Some(2), Some("M1".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_tools_no_machine_can_use() {
+        let machines = vec![Machine::new("M1", 60, vec![ToolType::T1])];
+        let mut plan = CapacityPlan::new(machines, vec![], 2, 0);
+        let mut steps = steps();
+
+        assert!(plan.schedule_forward(&mut steps).is_err());
+        assert!(plan
+            .booked
+            .values()
+            .flat_map(|t| t.values())
+            .all(|t| *t == 0));
+    }
+}
@@ -22,14 +22,6 @@ impl ItemBlueprint {
         Ok(())
     }
 
-    pub fn set_start(&mut self, starting_date: i64) {
-        self.process
-            .last_mut()
-            .expect("empty process")
-            .transf
-            .set_date(starting_date as i32);
-    }
-
     pub fn generate(
         item: Item,
         full_recipe: &[Recipe],
@@ -49,6 +41,16 @@ impl ItemBlueprint {
         &self.item
     }
 
+    /// Day on which the first step of the process is scheduled.
+    pub fn start_date(&self) -> Option<i32> {
+        self.process.last().and_then(|step| step.transf.date())
+    }
+
+    #[cfg(test)]
+    pub fn process(&self) -> &[item_handler::Step] {
+        &self.process
+    }
+
     pub fn process_mut(&mut self) -> &mut Vec<item_handler::Step> {
         &mut self.process
     }
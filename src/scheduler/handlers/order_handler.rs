@@ -1,8 +1,10 @@
 use anyhow::Result;
-use async_recursion::async_recursion;
 use uuid::Uuid;
 
-use crate::db_api::{Item, PieceKind, Recipe};
+use crate::{
+    db_api::{Item, Machine, PieceKind, Recipe},
+    scheduler::recipe_graph::RecipeGraph,
+};
 
 pub fn gen_items(
     piece: PieceKind,
@@ -22,32 +24,36 @@ pub fn gen_items(
     Ok(items)
 }
 
-#[async_recursion]
-pub(crate) async fn get_full_recipe(
+/// Time a recipe step is expected to take: its operation time plus the
+/// quickest changeover that mounts its tool on a machine able to use it.
+fn step_time(recipe: &Recipe, machines: &[Machine]) -> i64 {
+    let setup = machines
+        .iter()
+        .filter(|m| m.can_use(recipe.tool))
+        .map(|m| m.setup_time(recipe.tool))
+        .min()
+        .unwrap_or(0);
+    i64::from(recipe.operation_time) + setup
+}
+
+fn route_time(route: &[Recipe], machines: &[Machine]) -> i64 {
+    route.iter().map(|r| step_time(r, machines)).sum()
+}
+
+/// Every route from `piece` down to a raw material, quickest first.
+///
+/// Routes are ordered by their expected time, including tool setup, ties
+/// keeping the order of the recipe graph, so the first route is always the
+/// one with the least operation and tool setup time.
+pub(crate) fn get_recipe_routes(
     piece: PieceKind,
-    pool: &sqlx::PgPool,
-) -> Result<Vec<Recipe>> {
-    let recipes = Recipe::get_by_product(piece, pool).await?;
-    if recipes.is_empty() {
-        return Ok(vec![]);
-    }
-
-    let mut possible_paths = Vec::new();
-    for recipe in recipes {
-        let subrecipe = get_full_recipe(recipe.material_kind, pool).await?;
-        let mut recipe_path = vec![recipe];
-        recipe_path.extend(subrecipe);
-        possible_paths.push(recipe_path);
-    }
-
-    let best = possible_paths
-        .into_iter()
-        .min_by_key(|r| r.iter().map(|r| r.operation_time).sum::<i64>());
-
-    tracing::debug!("Best full recipe for piece {:?}: {:?}", piece, best);
-
-    match best {
-        Some(b) => Ok(b),
-        None => unreachable!(),
-    }
+    machines: &[Machine],
+    recipes: &RecipeGraph,
+) -> Vec<Vec<Recipe>> {
+    let mut routes = recipes.routes(piece).to_vec();
+    routes.sort_by_cached_key(|route| route_time(route, machines));
+
+    tracing::debug!("Recipe routes for piece {:?}: {:?}", piece, routes);
+
+    routes
 }
@@ -1,28 +1,50 @@
+mod capacity_planning;
 mod handlers;
+mod recipe_graph;
 mod resource_planning;
 
+pub use recipe_graph::RecipeGraph;
+
+use std::sync::{Arc, RwLock};
+
 use sqlx::{postgres::PgListener, PgPool};
 
 use crate::{
-    db_api::{self, Item, NotificationChannel as NotifCh, RawMaterial},
-    scheduler::handlers::{blueprint_handler::ItemBlueprint, order_handler},
+    configuration::SchedulerSettings,
+    db_api::{
+        self, Item, ItemMovement, NotificationChannel as NotifCh, RawMaterial,
+    },
+    scheduler::{capacity_planning::CapacityPlan, handlers::order_handler},
 };
 
-pub const TIME_IN_DAY: i64 = 60; // in the simulation, 1 day is 60 seconds
-
 pub struct Scheduler {
     pool: PgPool,
     listener: PgListener,
+    /// shared with the order intake, replaced when the recipes change
+    recipes: Arc<RwLock<RecipeGraph>>,
+    settings: SchedulerSettings,
 }
 
 impl Scheduler {
-    pub fn new(pool: PgPool, listener: PgListener) -> Self {
-        Self { pool, listener }
+    pub fn new(
+        pool: PgPool,
+        listener: PgListener,
+        recipes: Arc<RwLock<RecipeGraph>>,
+        settings: SchedulerSettings,
+    ) -> Self {
+        Self {
+            pool,
+            listener,
+            recipes,
+            settings,
+        }
     }
 
     async fn process_new_order(
         payload: impl ToString,
         pool: &PgPool,
+        recipes: &RwLock<RecipeGraph>,
+        settings: &SchedulerSettings,
     ) -> anyhow::Result<()> {
         let order_id = uuid::Uuid::parse_str(&payload.to_string())?;
 
@@ -33,8 +55,24 @@ impl Scheduler {
 
         tracing::debug!("Received new order: {:?}", order);
 
-        let full_recipe =
-            order_handler::get_full_recipe(order.piece(), pool).await?;
+        if order.status() != db_api::OrderStatus::Pending {
+            tracing::info!(
+                "Order {} is {}, skipping scheduling",
+                order.id(),
+                order.status()
+            );
+            return Ok(());
+        }
+
+        let machines = {
+            let mut con = pool.acquire().await?;
+            db_api::Machine::get_all(&mut con).await?
+        };
+
+        let routes = {
+            let recipes = recipes.read().expect("Recipe graph lock poisoned");
+            order_handler::get_recipe_routes(order.piece(), &machines, &recipes)
+        };
 
         let order_items: Vec<Item> = order_handler::gen_items(
             order.piece(),
@@ -42,67 +80,60 @@ impl Scheduler {
             Some(order.id()),
         )?;
 
-        tracing::debug!("Generated recipe: {:?}", full_recipe);
+        tracing::debug!("Generated recipe routes: {:?}", routes);
         tracing::debug!("Generated order items: {:?}", order_items);
 
         let current_date = {
             let mut con = pool.acquire().await?;
             db_api::get_date(&mut con).await?
-        } as i64;
-        // earliest start is the next day so that materials can be prepared
-        let earliest_start = current_date + 1;
-
-        let mut blueprints = order_items
-            .iter()
-            .filter_map(|item| {
-                match ItemBlueprint::generate((*item).clone(), &full_recipe) {
-                    Ok(bp) => Some(bp),
-                    Err(e) => {
-                        tracing::error!("{:?}", e);
-                        None
-                    }
-                }
-            })
-            .collect::<Vec<_>>();
-        assert_eq!(order_items.len(), blueprints.len());
-
-        //TODO: schedule the blueprints for production taking into account
-        //maximum factory parallelism capacity
-        let bp_time: i64 = full_recipe.iter().map(|r| r.operation_time).sum();
+        } as i32;
+        // leave time for the materials to be prepared
+        let earliest_start = current_date + settings.start_offset_days;
 
-        //TODO: make these constants config parameteres
-        //
-        // Assume that the factory can handle 3 blueprints in parallel at most
-        const PARALLELISM_CAPACITY: i64 = 3;
-        // assume a % of the needed time is spent on logistics instead of production
-        const LOGISTICS_TIME_FACTOR: i64 = 50;
+        let mut plan = {
+            let mut con = pool.acquire().await?;
+            CapacityPlan::load(
+                machines,
+                earliest_start,
+                settings.logistics_time_factor,
+                &mut con,
+            )
+            .await?
+        };
 
         // the last day that the order can be completed in order to be able to
         // be delivered on time
-        let completion_date = order.due_date() as i64 - 1;
-        let bp_time = bp_time + bp_time * LOGISTICS_TIME_FACTOR / 100;
-        let total_time = bp_time * order.quantity() as i64;
-        let days_needed = total_time / (TIME_IN_DAY * PARALLELISM_CAPACITY);
-        if days_needed > completion_date - earliest_start {
+        let completion_date = order.due_date() - 1;
+        let mut last_completion = earliest_start;
+        let mut blueprints = Vec::with_capacity(order_items.len());
+        for item in order_items.iter() {
+            let (bp, finish) =
+                plan.schedule_item(item, &routes, completion_date)?;
+            last_completion = last_completion.max(finish);
+            blueprints.push(bp);
+        }
+
+        if last_completion > completion_date {
             tracing::warn!(
                 "Order {} cannot be completed on time, not enough capacity",
                 order.id()
             );
         }
 
-        let starting_date = earliest_start.max(completion_date - days_needed);
-        blueprints
-            .iter_mut()
-            .for_each(|bp| bp.set_start(starting_date));
-
         let mut tx = pool.begin().await?;
+        ItemMovement::set_actor("scheduler", &mut tx).await?;
         for mut bp in blueprints {
             bp.insert_to_db(&mut tx).await?;
         }
 
         // order must be delivered on the last day of the schedule
-        // when all the items are ready for now, last day is the due date
-        order.schedule(order.due_date(), &mut tx).await?;
+        // when all the items are ready, but never before the due date
+        let delivery_day = order.due_date().max(last_completion + 1);
+        let res = order.schedule(delivery_day, &mut tx).await?;
+        if res.rows_affected() == 0 {
+            // the transaction is rolled back, no transformations are kept
+            anyhow::bail!("Order {} was canceled while scheduling", order.id());
+        }
 
         tx.commit().await?;
 
@@ -147,30 +178,63 @@ impl Scheduler {
         Ok(())
     }
 
+    async fn process_recipes_changed(
+        pool: &PgPool,
+        recipes: &RwLock<RecipeGraph>,
+    ) -> anyhow::Result<()> {
+        let mut con = pool.acquire().await?;
+        match RecipeGraph::load(&mut con).await {
+            Ok(graph) => {
+                *recipes.write().expect("Recipe graph lock poisoned") = graph;
+                tracing::info!("Reloaded recipe graph");
+                Ok(())
+            }
+            Err(e) => {
+                anyhow::bail!("Keeping previous recipe graph: {e}")
+            }
+        }
+    }
+
     pub async fn process_notif(
         notif: sqlx::postgres::PgNotification,
         pool: &PgPool,
+        recipes: &RwLock<RecipeGraph>,
+        settings: &SchedulerSettings,
     ) -> anyhow::Result<()> {
         match NotifCh::try_from(notif.channel())? {
             NotifCh::NewOrder => {
-                Self::process_new_order(notif.payload(), pool).await
+                Self::process_new_order(
+                    notif.payload(),
+                    pool,
+                    recipes,
+                    settings,
+                )
+                .await
             }
             NotifCh::MaterialsNeeded => {
-                tracing::info!(
-                    "Materials needed for order: {:?}",
-                    notif.payload()
-                );
+                // sent for a scheduled order or when the date changes
+                tracing::info!("Materials needed: {:?}", notif.payload());
                 Self::process_material_needs(notif.payload(), pool).await
             }
+            NotifCh::RecipesChanged => {
+                Self::process_recipes_changed(pool, recipes).await
+            }
         }
     }
 
-    pub async fn run(mut self) -> anyhow::Result<()> {
-        self.listener.listen(&NotifCh::NewOrder.to_string()).await?;
-        self.listener
+    /// Subscribes `listener` to the channels the scheduler handles.
+    ///
+    /// Must be called before the recipe graph is loaded, otherwise a recipe
+    /// change committed in between would never be picked up.
+    pub async fn listen(listener: &mut PgListener) -> sqlx::Result<()> {
+        listener.listen(&NotifCh::NewOrder.to_string()).await?;
+        listener
             .listen(&NotifCh::MaterialsNeeded.to_string())
             .await?;
+        listener.listen(&NotifCh::RecipesChanged.to_string()).await
+    }
 
+    pub async fn run(mut self) -> anyhow::Result<()> {
         loop {
             let notif = match self.listener.recv().await {
                 Ok(notif) => notif,
@@ -180,7 +244,14 @@ impl Scheduler {
                 }
             };
 
-            match Self::process_notif(notif, &self.pool).await {
+            match Self::process_notif(
+                notif,
+                &self.pool,
+                &self.recipes,
+                &self.settings,
+            )
+            .await
+            {
                 Ok(_) => (),
                 Err(e) => tracing::error!("{:?}", e),
             }
@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+
+use sqlx::PgConnection;
+
+use crate::db_api::{FinalPiece, PieceKind, RawMaterial, Recipe};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecipeGraphError {
+    Cycle(PieceKind),
+    UnreachableFinalPiece(FinalPiece),
+    NoRawMaterialLeaf(FinalPiece),
+}
+
+impl std::fmt::Display for RecipeGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecipeGraphError::Cycle(piece) => {
+                write!(f, "Recipes for {:?} form a cycle", piece)
+            }
+            RecipeGraphError::UnreachableFinalPiece(piece) => {
+                write!(f, "No recipe produces final piece {}", piece)
+            }
+            RecipeGraphError::NoRawMaterialLeaf(piece) => {
+                write!(
+                    f,
+                    "Final piece {} cannot be made from raw material",
+                    piece
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecipeGraphError {}
+
+/// In-memory view of the `recipes` table.
+///
+/// Every route from each final piece down to a raw material is computed once
+/// when the graph is built, quickest first, so that scheduling an order does
+/// not need to walk the recipes again.
+#[derive(Debug, Clone)]
+pub struct RecipeGraph {
+    by_product: HashMap<PieceKind, Vec<Recipe>>,
+    routes: HashMap<PieceKind, Vec<Vec<Recipe>>>,
+}
+
+impl RecipeGraph {
+    /// Builds the graph, failing if the recipes contain a cycle or if any
+    /// final piece cannot be produced from a raw material.
+    pub fn new(recipes: Vec<Recipe>) -> Result<Self, RecipeGraphError> {
+        let mut by_product: HashMap<PieceKind, Vec<Recipe>> = HashMap::new();
+        for recipe in recipes {
+            by_product
+                .entry(recipe.product_kind)
+                .or_default()
+                .push(recipe);
+        }
+
+        let mut graph = Self {
+            by_product,
+            routes: HashMap::new(),
+        };
+        graph.check_cycles()?;
+
+        for piece in enum_iterator::all::<FinalPiece>() {
+            if !graph.by_product.contains_key(&piece.into()) {
+                return Err(RecipeGraphError::UnreachableFinalPiece(piece));
+            }
+
+            let mut routes = graph
+                .routes_from(piece.into())
+                .into_iter()
+                .filter(|route| {
+                    route.last().is_some_and(|r| {
+                        RawMaterial::try_from(r.material_kind).is_ok()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            if routes.is_empty() {
+                return Err(RecipeGraphError::NoRawMaterialLeaf(piece));
+            }
+
+            routes.sort_by_cached_key(|route| {
+                let time: i64 =
+                    route.iter().map(|r| i64::from(r.operation_time)).sum();
+                let ids = route.iter().map(|r| r.id).collect::<Vec<_>>();
+                (time, ids)
+            });
+            graph.routes.insert(piece.into(), routes);
+        }
+
+        Ok(graph)
+    }
+
+    pub async fn load(con: &mut PgConnection) -> anyhow::Result<Self> {
+        let recipes = Recipe::get_all(con).await?;
+        Ok(Self::new(recipes)?)
+    }
+
+    /// Every route from `piece` down to a raw material, quickest first.
+    ///
+    /// Routes start with the recipe that yields `piece`. Only final pieces
+    /// have routes.
+    pub fn routes(&self, piece: PieceKind) -> &[Vec<Recipe>] {
+        self.routes.get(&piece).map_or(&[], |r| r.as_slice())
+    }
+
+    fn check_cycles(&self) -> Result<(), RecipeGraphError> {
+        fn visit(
+            graph: &RecipeGraph,
+            piece: PieceKind,
+            path: &mut Vec<PieceKind>,
+            done: &mut HashSet<PieceKind>,
+        ) -> Result<(), RecipeGraphError> {
+            if done.contains(&piece) {
+                return Ok(());
+            }
+            if path.contains(&piece) {
+                return Err(RecipeGraphError::Cycle(piece));
+            }
+
+            path.push(piece);
+            for recipe in graph.by_product.get(&piece).into_iter().flatten() {
+                visit(graph, recipe.material_kind, path, done)?;
+            }
+            path.pop();
+            done.insert(piece);
+
+            Ok(())
+        }
+
+        let mut done = HashSet::new();
+        for piece in self.by_product.keys() {
+            visit(self, *piece, &mut Vec::new(), &mut done)?;
+        }
+
+        Ok(())
+    }
+
+    fn routes_from(&self, piece: PieceKind) -> Vec<Vec<Recipe>> {
+        let Some(recipes) = self.by_product.get(&piece) else {
+            return vec![vec![]];
+        };
+
+        let mut routes = Vec::new();
+        for recipe in recipes {
+            for subroute in self.routes_from(recipe.material_kind) {
+                let mut route = vec![recipe.clone()];
+                route.extend(subroute);
+                routes.push(route);
+            }
+        }
+
+        routes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_api::ToolType;
+
+    fn recipe(
+        id: i64,
+        material_kind: PieceKind,
+        product_kind: PieceKind,
+        tool: ToolType,
+        operation_time: i32,
+    ) -> Recipe {
+        Recipe {
+            id,
+            material_kind,
+            product_kind,
+            tool,
+            operation_time,
+        }
+    }
+
+    // mirrors the recipes seeded by the migrations
+    fn seeded() -> Vec<Recipe> {
+        use PieceKind::*;
+        use ToolType::*;
+        vec![
+            recipe(1, P1, P3, T1, 45),
+            recipe(2, P3, P4, T2, 15),
+            recipe(3, P3, P4, T3, 25),
+            recipe(4, P4, P5, T4, 25),
+            recipe(5, P4, P6, T2, 25),
+            recipe(6, P4, P7, T3, 15),
+            recipe(7, P2, P8, T1, 45),
+            recipe(8, P8, P7, T6, 15),
+            recipe(9, P8, P9, T5, 45),
+        ]
+    }
+
+    fn ids(route: &[Recipe]) -> Vec<i64> {
+        route.iter().map(|r| r.id).collect()
+    }
+
+    #[test]
+    fn computes_routes_quickest_first() {
+        let graph = RecipeGraph::new(seeded()).unwrap();
+
+        let p5 = graph.routes(PieceKind::P5);
+        assert_eq!(
+            p5.iter().map(|r| ids(r)).collect::<Vec<_>>(),
+            vec![vec![4, 2, 1], vec![4, 3, 1]]
+        );
+
+        let p7 = graph.routes(PieceKind::P7);
+        assert_eq!(ids(&p7[0]), vec![8, 7]);
+        assert_eq!(p7.len(), 3);
+
+        assert!(graph.routes(PieceKind::P4).is_empty());
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let mut recipes = seeded();
+        let back_edge =
+            recipe(10, PieceKind::P4, PieceKind::P3, ToolType::T1, 5);
+        recipes.push(back_edge);
+
+        assert!(matches!(
+            RecipeGraph::new(recipes),
+            Err(RecipeGraphError::Cycle(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unreachable_final_pieces() {
+        let recipes = seeded().into_iter().filter(|r| r.id != 9).collect();
+
+        assert_eq!(
+            RecipeGraph::new(recipes).unwrap_err(),
+            RecipeGraphError::UnreachableFinalPiece(FinalPiece::P9)
+        );
+    }
+
+    #[test]
+    fn rejects_final_pieces_without_raw_material() {
+        let recipes = seeded().into_iter().filter(|r| r.id != 7).collect();
+
+        assert_eq!(
+            RecipeGraph::new(recipes).unwrap_err(),
+            RecipeGraphError::NoRawMaterialLeaf(FinalPiece::P9)
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use sqlx::PgPool;
 
 use crate::db_api::{
-    MaterialShipment, RawMaterial, Shipment, Supplier, UnderAllocatedShipment,
+    MaterialShipment, RawMaterial, Shipment, StockProjection, Supplier,
+    UnderAllocatedShipment,
 };
 
 struct DayVariantNeedsData {
@@ -10,6 +11,7 @@ struct DayVariantNeedsData {
     pub variant: RawMaterial,
     pub under_allocated: Vec<UnderAllocatedShipment>,
     pub suppliers: Vec<Supplier>,
+    pub projection: StockProjection,
 }
 
 async fn resolve_day_needs(
@@ -96,6 +98,7 @@ async fn resolve_day_needs(
 struct QueryResults {
     pub shipments: Vec<UnderAllocatedShipment>,
     pub suppliers: Vec<Supplier>,
+    pub projection: StockProjection,
 }
 
 async fn query_needed_data(
@@ -110,16 +113,22 @@ async fn query_needed_data(
     let shipments =
         Shipment::get_under_allocated(due_date, variant, &mut conn).await?;
 
+    // loaded for every day, so that it includes the shipments bought for
+    // the previous ones
+    let current_date = crate::db_api::get_date(&mut conn).await?;
+    let projection =
+        StockProjection::load(current_date as i32, &mut conn).await?;
+
     let query_results = QueryResults {
         shipments,
         suppliers,
+        projection,
     };
 
     Ok(query_results)
 }
 
-// TODO: Take warehouse capacity into account
-// Test if underallocated shipments are being processed correctly
+// TODO: Test if underallocated shipments are being processed correctly
 pub async fn resolve_material_needs(
     variant: RawMaterial,
     pool: PgPool,
@@ -156,6 +165,7 @@ pub async fn resolve_material_needs(
             variant,
             under_allocated: qr.shipments,
             suppliers: qr.suppliers,
+            projection: qr.projection,
         };
         resolve_day_needs(&pool, needs_data).await?;
     }
@@ -214,29 +224,117 @@ fn process_purchases(
         };
     }
 
+    // existing shipments cover the needs, buying the minimum order quantity
+    // would only fill the warehouses
+    if needs.net_req == 0 {
+        return PurchaseProcessingResults {
+            purchase_order: None,
+            altered_shipments,
+        };
+    }
+
+    // The items allocated to existing shipments leave the warehouses on the
+    // due date as well.
+    let allocated = altered_shipments.iter().map(|s| s.added).sum();
+    needs.projection.add_consumption(needs.due_date, allocated);
+
+    // Shipments arrive on the due date, as late as possible, so only their
+    // size changes how full the warehouses get. Extra items bought to reach
+    // the minimum order quantity stay in stock, so prefer the cheapest
+    // purchase that fits in the warehouses and otherwise the one that
+    // overfills them the least.
     let suppliers = needs.suppliers.clone();
-    let cheapest_purchase = suppliers
+    let purchase = suppliers
         .into_iter()
         .filter_map(|s| match s.can_deliver_in(available_time) {
             true => Some(s.shipment(needs.net_req, needs.due_date)),
             false => None,
         })
-        .min_by_key(|shipment| shipment.cost().0);
-
-    if cheapest_purchase.is_none() {
-        tracing::warn!(
-            "No supplier can deliver {:#?} in time for day {}",
-            needs.variant,
-            needs.due_date
-        );
-    }
+        .map(|shipment| {
+            let overflow = needs.projection.overflow(
+                needs.due_date,
+                shipment.quantity() as i64,
+                needs.net_req as i64,
+            );
+            (overflow, shipment)
+        })
+        .min_by_key(|(overflow, shipment)| (*overflow, shipment.cost().0));
+
+    // Items bought on a later day still arrive on the due date, by when the
+    // warehouses may have emptied, e.g. through deliveries. So while there is
+    // time, only the part of the requirement that fits is bought now and the
+    // rest stays pending until materials are planned again.
+    let can_wait = needs
+        .suppliers
+        .iter()
+        .any(|s| s.can_deliver_in(available_time - 1));
+    let purchase = match purchase {
+        Some((overflow, _)) if overflow > 0 && can_wait => {
+            let part = fitting_purchase(needs, available_time);
+            let bought = part.as_ref().map_or(0, |(quantity, _)| *quantity);
+            tracing::info!(
+                "Buying {} of {} {:#?} for day {}, the rest is bought later",
+                bought,
+                needs.net_req,
+                needs.variant,
+                needs.due_date
+            );
+            needs.net_req = bought;
+            part.map(|(_, shipment)| shipment)
+        }
+        Some((overflow, shipment)) => {
+            if overflow > 0 {
+                tracing::warn!(
+                    "Warehouses will hold {} items over capacity after buying {} {:#?} for day {}",
+                    overflow,
+                    shipment.quantity(),
+                    needs.variant,
+                    needs.due_date
+                );
+            }
+            Some(shipment)
+        }
+        None => {
+            tracing::warn!(
+                "No supplier can deliver {:#?} in time for day {}",
+                needs.variant,
+                needs.due_date
+            );
+            None
+        }
+    };
 
     PurchaseProcessingResults {
-        purchase_order: cheapest_purchase,
+        purchase_order: purchase,
         altered_shipments,
     }
 }
 
+/// Largest part of the net requirement that can be bought without
+/// overfilling the warehouses, from the cheapest supplier able to deliver it.
+fn fitting_purchase(
+    needs: &DayVariantNeedsData,
+    available_time: i32,
+) -> Option<(i32, Shipment)> {
+    (1..needs.net_req).rev().find_map(|part| {
+        needs
+            .suppliers
+            .iter()
+            .filter(|s| s.can_deliver_in(available_time))
+            .map(|s| s.shipment(part, needs.due_date))
+            .filter(|shipment| {
+                let overflow = needs.projection.overflow(
+                    needs.due_date,
+                    shipment.quantity() as i64,
+                    part as i64,
+                );
+                overflow == 0
+            })
+            .min_by_key(|shipment| shipment.cost().0)
+            .map(|shipment| (part, shipment))
+    })
+}
+
 fn process_under_alocated_shipments(
     net_req: &mut i32,
     under_allocated: &mut Vec<UnderAllocatedShipment>,
@@ -261,3 +359,51 @@ fn process_under_alocated_shipments(
     // remove shipments to which nothing was allocated
     under_allocated.retain(|s| s.added.is_some());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day_needs(net_req: i32, occupancy: i64) -> DayVariantNeedsData {
+        DayVariantNeedsData {
+            due_date: 6,
+            net_req,
+            variant: RawMaterial::P1,
+            under_allocated: Vec::new(),
+            suppliers: vec![
+                Supplier::new(1, RawMaterial::P1, 16, 30, 4),
+                Supplier::new(2, RawMaterial::P1, 4, 55, 1),
+            ],
+            projection: StockProjection::new(64, occupancy, 0),
+        }
+    }
+
+    #[test]
+    fn purchases_that_fit_are_bought_whole() {
+        let mut needs = day_needs(12, 40);
+        let pr = process_purchases(&mut needs, 0);
+
+        let shipment = pr.purchase_order.expect("Nothing bought");
+        assert_eq!(shipment.quantity(), 16);
+        assert_eq!(needs.net_req, 12);
+    }
+
+    #[test]
+    fn purchases_that_overflow_are_split() {
+        // 10 free slots, the rest is bought on a later day
+        let mut needs = day_needs(12, 54);
+        let pr = process_purchases(&mut needs, 0);
+
+        let shipment = pr.purchase_order.expect("Nothing bought");
+        assert_eq!(shipment.quantity(), 10);
+        assert_eq!(needs.net_req, 10);
+
+        // no time left to wait, everything is bought despite the overflow
+        let mut needs = day_needs(12, 54);
+        let pr = process_purchases(&mut needs, 5);
+
+        let shipment = pr.purchase_order.expect("Nothing bought");
+        assert_eq!(shipment.quantity(), 12);
+        assert_eq!(needs.net_req, 12);
+    }
+}
@@ -1,17 +1,36 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+};
+
 use actix_web::{web::Data, HttpServer};
 use anyhow::anyhow;
 use sqlx::PgPool;
-use tokio::net::UdpSocket;
+use tokio::net::{TcpListener, UdpSocket};
 use tracing::Level;
 
-use crate::{routes, scheduler::Scheduler, udp_listener::Listener};
+use crate::{
+    configuration::{PricingSettings, SchedulerSettings, ValidationSettings},
+    db_api,
+    intake::Intake,
+    routes,
+    scheduler::{RecipeGraph, Scheduler},
+    tcp_listener, udp_listener,
+};
 
 pub struct AppBuilder {
     tracing_level: Level,
     database_url: String,
-    udp_addr: Option<String>,
+    udp_addrs: Vec<SocketAddr>,
     udp_buffer_size: Option<usize>,
+    udp_allowed_sources: Vec<IpAddr>,
+    tcp_addrs: Vec<SocketAddr>,
+    tcp_max_message_size: Option<usize>,
     http_addr: Option<String>,
+    http_order_intake: bool,
+    pricing: PricingSettings,
+    validation: ValidationSettings,
+    scheduler: SchedulerSettings,
 }
 
 impl AppBuilder {
@@ -19,23 +38,83 @@ impl AppBuilder {
         Self {
             tracing_level: Level::ERROR,
             database_url,
-            udp_addr: None,
+            udp_addrs: Vec::new(),
             udp_buffer_size: None,
+            udp_allowed_sources: Vec::new(),
+            tcp_addrs: Vec::new(),
+            tcp_max_message_size: None,
             http_addr: None,
+            http_order_intake: false,
+            pricing: PricingSettings::default(),
+            validation: ValidationSettings::default(),
+            scheduler: SchedulerSettings::default(),
         }
     }
 
-    pub fn with_udp_listener(mut self, port: u16, buffer_size: usize) -> Self {
-        self.udp_addr = Some(format!("127.0.0.1:{}", port));
+    /// Listens on `port` of every host in `hosts`.
+    pub fn with_udp_listener(
+        mut self,
+        hosts: &[IpAddr],
+        port: u16,
+        buffer_size: usize,
+    ) -> Self {
+        self.udp_addrs = hosts
+            .iter()
+            .map(|host| SocketAddr::new(*host, port))
+            .collect();
         self.udp_buffer_size = Some(buffer_size);
         self
     }
 
+    /// Drop UDP commands that are not sent from one of `sources`, accepting
+    /// commands from anywhere if it is empty.
+    pub fn with_udp_allowed_sources(mut self, sources: Vec<IpAddr>) -> Self {
+        self.udp_allowed_sources = sources;
+        self
+    }
+
+    /// Listens on `port` of every host in `hosts`. Commands sent over TCP
+    /// must be prefixed by their length, see [`tcp_listener::Listener`].
+    pub fn with_tcp_listener(
+        mut self,
+        hosts: &[IpAddr],
+        port: u16,
+        max_message_size: usize,
+    ) -> Self {
+        self.tcp_addrs = hosts
+            .iter()
+            .map(|host| SocketAddr::new(*host, port))
+            .collect();
+        self.tcp_max_message_size = Some(max_message_size);
+        self
+    }
+
     pub fn with_web_server(mut self, http_host: &str, http_port: u16) -> Self {
         self.http_addr = Some(format!("{}:{}", http_host, http_port));
         self
     }
 
+    /// Accept orders on `POST /orders`, only used with a web server.
+    pub fn with_http_order_intake(mut self) -> Self {
+        self.http_order_intake = true;
+        self
+    }
+
+    pub fn with_pricing(mut self, pricing: PricingSettings) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    pub fn with_validation(mut self, validation: ValidationSettings) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub fn with_scheduler(mut self, scheduler: SchedulerSettings) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
     pub fn with_tracing_level(mut self, level: Level) -> Self {
         self.tracing_level = level;
         self
@@ -53,42 +132,120 @@ impl AppBuilder {
             tracing::error!("Error running migrations: {e}");
             return Err(anyhow!(e));
         }
-        let notification_listener =
+        let mut notification_listener =
             sqlx::postgres::PgListener::connect(&self.database_url).await?;
+        Scheduler::listen(&mut notification_listener).await?;
+
+        {
+            let mut con = pool.acquire().await?;
+            db_api::set_holding_cost_rate(
+                self.scheduler.holding_cost_rate,
+                &mut con,
+            )
+            .await?;
+            db_api::Machine::set_day_capacity(
+                self.scheduler.day_capacity,
+                &mut con,
+            )
+            .await?;
+        }
 
         tracing::info!("DB initialization successfull.");
 
-        let udp_listener = if let (Some(address), Some(buffer_size)) =
-            (self.udp_addr, self.udp_buffer_size)
-        {
-            let socket = UdpSocket::bind(&address).await?;
-            let listener = Listener::new(pool.clone(), socket, buffer_size);
-            Some(listener)
-        } else {
-            None
+        let recipes = {
+            let mut con = pool.acquire().await?;
+            match RecipeGraph::load(&mut con).await {
+                Ok(graph) => Arc::new(RwLock::new(graph)),
+                Err(e) => {
+                    tracing::error!("Invalid recipes: {e}");
+                    return Err(e);
+                }
+            }
         };
 
-        let scheduler = Scheduler::new(pool.clone(), notification_listener);
+        let intake = Intake::new(
+            pool.clone(),
+            self.pricing,
+            self.validation,
+            recipes.clone(),
+        );
+
+        let mut udp_listeners = Vec::new();
+        if let Some(buffer_size) = self.udp_buffer_size {
+            for address in self.udp_addrs {
+                let socket = match UdpSocket::bind(address).await {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        anyhow::bail!("Error binding to address: {address}")
+                    }
+                };
+                let listener = udp_listener::Listener::new(
+                    intake.clone(),
+                    socket,
+                    buffer_size,
+                )
+                .with_allowed_sources(self.udp_allowed_sources.clone());
+                udp_listeners.push(listener);
+            }
+        }
+
+        let mut tcp_listeners = Vec::new();
+        if let Some(max_message_size) = self.tcp_max_message_size {
+            for address in self.tcp_addrs {
+                let listener = match TcpListener::bind(address).await {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        anyhow::bail!("Error binding to address: {address}")
+                    }
+                };
+                tcp_listeners.push(tcp_listener::Listener::new(
+                    intake.clone(),
+                    listener,
+                    max_message_size,
+                ));
+            }
+        }
+
+        let scheduler = Scheduler::new(
+            pool.clone(),
+            notification_listener,
+            recipes,
+            self.scheduler,
+        );
 
         Ok(App {
             web_addr: self.http_addr,
             pool,
-            udp_listener,
+            udp_listeners,
+            tcp_listeners,
+            http_intake: self.http_order_intake.then_some(intake),
             scheduler,
         })
     }
 }
 
 pub struct App {
-    udp_listener: Option<Listener>,
+    udp_listeners: Vec<udp_listener::Listener>,
+    tcp_listeners: Vec<tcp_listener::Listener>,
     web_addr: Option<String>,
+    http_intake: Option<Intake>,
     pool: PgPool,
     scheduler: Scheduler,
 }
 
 impl App {
     pub async fn run(self) -> anyhow::Result<()> {
-        if let Some(listener) = self.udp_listener {
+        for listener in self.udp_listeners {
+            tokio::spawn(async move {
+                if let Err(e) = listener.listen().await {
+                    tracing::error!("{e}");
+                }
+            });
+        }
+
+        for listener in self.tcp_listeners {
             tokio::spawn(async move {
                 if let Err(e) = listener.listen().await {
                     tracing::error!("{e}");
@@ -102,17 +259,41 @@ impl App {
             let server = match HttpServer::new(move || {
                 actix_web::App::new()
                     .wrap(actix_web::middleware::Logger::default())
+                    .configure(routes::configure_extractor_errors)
                     .service(routes::check_health)
                     .service(routes::get_date)
                     .service(routes::post_date)
                     .service(routes::get_production)
+                    .service(routes::get_daily_transformations)
                     .service(routes::post_transformation_completion)
                     .service(routes::post_warehouse_action)
+                    .service(routes::get_warehouses)
+                    .service(routes::get_item)
+                    .service(routes::get_item_history)
+                    .service(routes::get_inventory)
                     .service(routes::get_expected_shipments)
                     .service(routes::post_material_arrival)
                     .service(routes::get_deliveries)
                     .service(routes::post_delivery_confirmation)
                     .service(routes::post_delivery_statistics)
+                    .service(routes::get_orders)
+                    .service(routes::get_order)
+                    .service(routes::get_order_cost)
+                    .service(routes::get_cost_report)
+                    .service(routes::get_profit_report)
+                    .service(routes::post_order_cancel)
+                    .service(routes::get_order_conflicts)
+                    .service(routes::get_recipes)
+                    .service(routes::post_recipe)
+                    .service(routes::put_recipe)
+                    .service(routes::delete_recipe)
+                    .configure(|cfg| {
+                        if let Some(intake) = &self.http_intake {
+                            cfg.app_data(Data::new(intake.clone()))
+                                .service(routes::post_orders_json)
+                                .service(routes::post_orders_xml);
+                        }
+                    })
                     .app_data(Data::new(self.pool.clone()))
             })
             .bind(addr.clone())
@@ -0,0 +1,156 @@
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::intake::{Acknowledgement, Intake, ParseError};
+
+/// Receives commands over TCP connections.
+///
+/// Every command and acknowledgement is framed by its length in bytes, as a
+/// big endian u32, so that a connection can carry several commands.
+pub struct Listener {
+    intake: Intake,
+    listener: TcpListener,
+    max_message_size: usize,
+}
+
+impl Listener {
+    pub fn new(
+        intake: Intake,
+        listener: TcpListener,
+        max_message_size: usize,
+    ) -> Self {
+        Self {
+            intake,
+            listener,
+            max_message_size,
+        }
+    }
+
+    pub async fn listen(self) -> anyhow::Result<()> {
+        tracing::info!(
+            "Listening for TCP connections on {}",
+            self.listener.local_addr()?
+        );
+        loop {
+            let (stream, addr) = self.listener.accept().await?;
+            tracing::info!("Accepted tcp connection from {}", addr);
+
+            let intake = self.intake.clone();
+            let max_message_size = self.max_message_size;
+            tokio::spawn(async move {
+                if let Err(e) =
+                    serve(intake, stream, addr, max_message_size).await
+                {
+                    tracing::error!("Connection with {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve(
+    intake: Intake,
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    max_message_size: usize,
+) -> anyhow::Result<()> {
+    loop {
+        let message = match read_frame(&mut stream, max_message_size).await? {
+            Frame::Message(message) => message,
+            Frame::TooLarge(len) => {
+                tracing::error!("Command of {} bytes from {}", len, addr);
+                let ack = Acknowledgement::rejected(ParseError {
+                    line: 1,
+                    column: 1,
+                    order_number: None,
+                    message: format!(
+                        "command of {} bytes is larger than {} bytes",
+                        len, max_message_size
+                    ),
+                });
+                // the rest of the stream cannot be trusted to be framed
+                return write_frame(&mut stream, ack.to_xml().as_bytes()).await;
+            }
+            Frame::Closed => return Ok(()),
+        };
+        tracing::info!("Received tcp message from {}", addr);
+
+        let ack = intake.receive_bytes(&message).await;
+        write_frame(&mut stream, ack.to_xml().as_bytes()).await?;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    Message(Vec<u8>),
+    /// length of a message that was not read
+    TooLarge(usize),
+    /// the peer closed the connection between two messages
+    Closed,
+}
+
+async fn read_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+    max_len: usize,
+) -> std::io::Result<Frame> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Ok(Frame::Closed)
+        }
+        Err(e) => return Err(e),
+    };
+
+    if len > max_len {
+        return Ok(Frame::TooLarge(len));
+    }
+
+    let mut message = vec![0; len];
+    reader.read_exact(&mut message).await?;
+    Ok(Frame::Message(message))
+}
+
+async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &[u8],
+) -> anyhow::Result<()> {
+    writer.write_u32(u32::try_from(message.len())?).await?;
+    writer.write_all(message).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_are_length_prefixed() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+
+        write_frame(&mut client, b"<DOCUMENT/>").await.unwrap();
+        write_frame(&mut client, &[b'a'; 20]).await.unwrap();
+        drop(client);
+
+        assert_eq!(
+            read_frame(&mut server, 16).await.unwrap(),
+            Frame::Message(b"<DOCUMENT/>".to_vec())
+        );
+        assert_eq!(
+            read_frame(&mut server, 16).await.unwrap(),
+            Frame::TooLarge(20)
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_between_frames() {
+        let (client, mut server) = tokio::io::duplex(64);
+        drop(client);
+
+        assert_eq!(read_frame(&mut server, 16).await.unwrap(), Frame::Closed);
+    }
+}
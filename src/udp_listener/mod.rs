@@ -1,24 +1,35 @@
-mod parser;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use tokio::net::UdpSocket;
+
+use crate::intake::{Acknowledgement, Intake};
 
 pub struct Listener {
-    pool: sqlx::PgPool,
-    socket: tokio::net::UdpSocket,
+    intake: Intake,
+    socket: Arc<UdpSocket>,
     buffer: Vec<u8>,
+    /// accept commands from any address when empty
+    allowed_sources: Vec<IpAddr>,
 }
 
 impl Listener {
-    pub fn new(
-        pool: sqlx::PgPool,
-        socket: tokio::net::UdpSocket,
-        buf_size: usize,
-    ) -> Self {
+    pub fn new(intake: Intake, socket: UdpSocket, buf_size: usize) -> Self {
         Self {
-            pool,
-            socket,
+            intake,
+            socket: Arc::new(socket),
             buffer: vec![0; buf_size],
+            allowed_sources: Vec::new(),
         }
     }
 
+    pub fn with_allowed_sources(mut self, sources: Vec<IpAddr>) -> Self {
+        self.allowed_sources = sources;
+        self
+    }
+
     pub async fn listen(mut self) -> anyhow::Result<()> {
         tracing::info!(
             "Listening for UDP messages on {}",
@@ -26,28 +37,53 @@ impl Listener {
         );
         loop {
             let (len, addr) = self.socket.recv_from(&mut self.buffer).await?;
-            let message = std::str::from_utf8(&self.buffer[..len])?;
-
+            if !is_allowed(&self.allowed_sources, addr.ip()) {
+                tracing::warn!("Dropped udp message from {}", addr);
+                continue;
+            }
             tracing::info!("Received udp message from {}", addr);
-            tracing::trace!("Received message: {}", message);
 
-            let (_, orders) = match parser::parse_command(message) {
-                Ok(o) => o,
-                Err(e) => {
-                    tracing::error!("{e} while parsing orders");
-                    continue;
-                }
-            };
-
-            let pool = self.pool.clone();
+            let message = self.buffer[..len].to_vec();
+            let intake = self.intake.clone();
+            let socket = self.socket.clone();
             tokio::spawn(async move {
-                for order in orders.into_iter() {
-                    match order.insert_to_db(&pool).await {
-                        Ok(id) => tracing::info!("Inserted order id: {}", id),
-                        Err(e) => tracing::error!("{:?}", e),
-                    }
-                }
+                let ack = intake.receive_bytes(&message).await;
+                reply(&socket, addr, &ack).await;
             });
         }
     }
 }
+
+/// Whether `source` may send commands, everyone may if `allowed` is empty.
+fn is_allowed(allowed: &[IpAddr], source: IpAddr) -> bool {
+    // IPv4 senders reach IPv6 sockets with mapped addresses
+    let source = source.to_canonical();
+    allowed.is_empty() || allowed.iter().any(|a| a.to_canonical() == source)
+}
+
+async fn reply(socket: &UdpSocket, addr: SocketAddr, ack: &Acknowledgement) {
+    if let Err(e) = socket.send_to(ack.to_xml().as_bytes(), addr).await {
+        tracing::error!("Failed to acknowledge command from {}: {}", addr, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn allowed_sources() {
+        let mes = Ipv4Addr::new(10, 0, 0, 7);
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 8));
+        assert!(is_allowed(&[], other));
+
+        let allowed = [IpAddr::V4(mes)];
+        assert!(is_allowed(&allowed, IpAddr::V4(mes)));
+        assert!(!is_allowed(&allowed, other));
+        // as seen by a socket bound to an IPv6 address
+        assert!(is_allowed(&allowed, IpAddr::V6(mes.to_ipv6_mapped())));
+        assert!(!is_allowed(&allowed, IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+}
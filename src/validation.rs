@@ -0,0 +1,255 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use sqlx::PgConnection;
+
+use crate::{
+    configuration::{ValidationPolicy, ValidationSettings},
+    db_api::{self, ClientOrder, FinalPiece, Machine, RawMaterial, Supplier},
+    scheduler::RecipeGraph,
+};
+
+/// What the validation rules need to know about the plant, loaded once per
+/// received command. The recipes come from the graph kept by the scheduler.
+#[derive(Debug, Clone)]
+pub struct ValidationContext {
+    current_date: i32,
+    /// quickest supplier delivery time of each raw material
+    delivery_times: HashMap<RawMaterial, i32>,
+    /// raw material and total operation time of every route of each piece
+    routes: HashMap<FinalPiece, Vec<(RawMaterial, i64)>>,
+    /// production time available per day on all machines
+    day_capacity: i64,
+}
+
+/// Rules broken by an order, sorted by what their policy says to do.
+///
+/// Every entry is a rule code followed by a description.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Validation {
+    pub rejections: Vec<String>,
+    pub warnings: Vec<String>,
+    /// codes of the rules to flag the order with
+    pub flags: Vec<String>,
+}
+
+impl Validation {
+    pub fn is_rejected(&self) -> bool {
+        !self.rejections.is_empty()
+    }
+
+    fn add(&mut self, policy: ValidationPolicy, code: &str, message: String) {
+        let message = format!("{}: {}", code, message);
+        match policy {
+            ValidationPolicy::Reject => self.rejections.push(message),
+            ValidationPolicy::Warn => self.warnings.push(message),
+            ValidationPolicy::Flag => {
+                self.warnings.push(message);
+                self.flags.push(code.to_string());
+            }
+        }
+    }
+}
+
+impl ValidationContext {
+    pub async fn load(
+        recipes: &RwLock<RecipeGraph>,
+        con: &mut PgConnection,
+    ) -> anyhow::Result<Self> {
+        let current_date = db_api::get_date(con).await? as i32;
+        let delivery_times = Supplier::get_quickest_deliveries(con).await?;
+        let day_capacity = Machine::get_all(con)
+            .await?
+            .iter()
+            .map(|m| m.day_capacity())
+            .sum();
+
+        let graph = recipes.read().expect("Recipe graph lock poisoned");
+        let mut routes = HashMap::new();
+        for piece in enum_iterator::all::<FinalPiece>() {
+            let piece_routes = graph
+                .routes(piece.into())
+                .iter()
+                .filter_map(|route| {
+                    let material =
+                        RawMaterial::try_from(route.last()?.material_kind)
+                            .ok()?;
+                    let time =
+                        route.iter().map(|r| i64::from(r.operation_time)).sum();
+                    Some((material, time))
+                })
+                .collect();
+            routes.insert(piece, piece_routes);
+        }
+
+        Ok(Self {
+            current_date,
+            delivery_times,
+            routes,
+            day_capacity,
+        })
+    }
+
+    /// First day an order could be delivered, if it can be produced at all.
+    ///
+    /// Assumes the material is bought today from the quickest supplier, that
+    /// all machines work on the order and that it is delivered the day after
+    /// its production ends.
+    pub fn earliest_delivery(&self, order: &ClientOrder) -> Option<i32> {
+        if self.day_capacity <= 0 {
+            return None;
+        }
+
+        self.routes
+            .get(&order.work_piece)?
+            .iter()
+            .filter_map(|(material, time)| {
+                let delivery_time = *self.delivery_times.get(material)?;
+                let work = time * order.quantity as i64;
+                let production_days =
+                    (work + self.day_capacity - 1) / self.day_capacity;
+                Some(delivery_time + production_days.max(1) as i32 + 1)
+            })
+            .min()
+            .map(|lead_time| self.current_date + lead_time)
+    }
+
+    pub fn validate(
+        &self,
+        order: &ClientOrder,
+        settings: &ValidationSettings,
+    ) -> Validation {
+        let mut validation = Validation::default();
+
+        if order.quantity < settings.min_quantity
+            || order.quantity > settings.max_quantity
+        {
+            validation.add(
+                settings.quantity,
+                "quantity",
+                format!(
+                    "quantity {} is not between {} and {}",
+                    order.quantity,
+                    settings.min_quantity,
+                    settings.max_quantity
+                ),
+            );
+        }
+
+        if order.due_date <= self.current_date {
+            validation.add(
+                settings.due_date,
+                "due_date",
+                format!(
+                    "due date {} is not after day {}",
+                    order.due_date, self.current_date
+                ),
+            );
+        } else {
+            match self.earliest_delivery(order) {
+                Some(day) if order.due_date < day => validation.add(
+                    settings.lead_time,
+                    "lead_time",
+                    format!(
+                        "due date {} is before the earliest delivery on day {}",
+                        order.due_date, day
+                    ),
+                ),
+                Some(_) => (),
+                None => validation.add(
+                    settings.lead_time,
+                    "lead_time",
+                    format!("{} cannot be produced", order.work_piece),
+                ),
+            }
+        }
+
+        if order.late_penalty < 0 || order.early_penalty < 0 {
+            validation.add(
+                settings.penalties,
+                "penalties",
+                "penalties cannot be negative".to_string(),
+            );
+        }
+
+        validation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> ValidationContext {
+        ValidationContext {
+            current_date: 10,
+            delivery_times: HashMap::from([
+                (RawMaterial::P1, 4),
+                (RawMaterial::P2, 1),
+            ]),
+            routes: HashMap::from([
+                (FinalPiece::P5, vec![(RawMaterial::P1, 60)]),
+                (
+                    FinalPiece::P6,
+                    vec![(RawMaterial::P1, 30), (RawMaterial::P2, 120)],
+                ),
+            ]),
+            day_capacity: 240,
+        }
+    }
+
+    fn order(piece: FinalPiece, quantity: i32, due_date: i32) -> ClientOrder {
+        ClientOrder::new(
+            "Client".to_string(),
+            1,
+            piece,
+            quantity,
+            due_date,
+            0,
+            0,
+        )
+    }
+
+    #[test]
+    fn earliest_delivery_takes_quickest_route() {
+        let ctx = context();
+
+        // 4 days for delivery, 1 day of production, delivered the next day
+        assert_eq!(
+            ctx.earliest_delivery(&order(FinalPiece::P5, 4, 30)),
+            Some(16)
+        );
+        // 8 pieces take 2 days of production
+        assert_eq!(
+            ctx.earliest_delivery(&order(FinalPiece::P5, 8, 30)),
+            Some(17)
+        );
+        // the slower route uses a material that arrives sooner
+        assert_eq!(
+            ctx.earliest_delivery(&order(FinalPiece::P6, 2, 30)),
+            Some(13)
+        );
+        assert_eq!(ctx.earliest_delivery(&order(FinalPiece::P7, 2, 30)), None);
+    }
+
+    #[test]
+    fn policies_sort_broken_rules() {
+        let ctx = context();
+        let settings = ValidationSettings {
+            quantity: ValidationPolicy::Warn,
+            ..Default::default()
+        };
+
+        let valid = ctx.validate(&order(FinalPiece::P5, 4, 20), &settings);
+        assert_eq!(valid, Validation::default());
+
+        let late = ctx.validate(&order(FinalPiece::P5, 30, 12), &settings);
+        assert!(!late.is_rejected());
+        assert_eq!(late.warnings.len(), 2);
+        assert_eq!(late.flags, vec!["lead_time".to_string()]);
+
+        let past = ctx.validate(&order(FinalPiece::P5, 4, 10), &settings);
+        assert!(past.is_rejected());
+        assert!(past.rejections[0].starts_with("due_date"));
+        assert!(past.flags.is_empty());
+    }
+}